@@ -0,0 +1,250 @@
+use std::path::PathBuf;
+
+use clap::{Arg, ArgAction, ArgMatches, Command, value_parser};
+
+use crate::config::{InterfaceOverride, LogFormat, Mode, Overrides};
+use crate::dbus_listener::DEFAULT_BUS_NAME;
+use crate::event_handler::Interface;
+
+/// Command line of the daemon.
+#[derive(Debug, Clone)]
+pub struct Cli {
+    /// Config file to read instead of searching the default locations.
+    pub config: Option<PathBuf>,
+    pub overrides: Overrides,
+    /// Well-known name the portal interfaces are served under.
+    pub bus_name: String,
+    /// Take the bus name over from a running instance.
+    pub replace: bool,
+    /// Only validate the configuration and print it.
+    pub dry_run: bool,
+    /// Manage the stored grants instead of running the daemon.
+    pub grants: Option<GrantsCommand>,
+}
+
+/// `grants` subcommands.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GrantsCommand {
+    List,
+    /// Forget the grant of this app id.
+    Revoke(String),
+}
+
+impl Cli {
+    pub fn parse() -> Self {
+        Self::from_matches(&command().get_matches())
+    }
+
+    fn from_matches(matches: &ArgMatches) -> Self {
+        let interfaces = Interface::ALL
+            .into_iter()
+            .map(|interface| {
+                let name = arg_name(interface);
+                let overrides = InterfaceOverride {
+                    mode: matches
+                        .get_one::<String>(&format!("{name}-mode"))
+                        .map(|mode| match mode.as_str() {
+                            "proxy" => Mode::Proxy,
+                            _ => Mode::Server,
+                        }),
+                    destination: matches
+                        .get_one::<String>(&format!("{name}-proxy"))
+                        .map(|destination| split_destination(destination)),
+                };
+                (interface, overrides)
+            })
+            .collect();
+
+        Self {
+            config: matches.get_one::<PathBuf>("config").cloned(),
+            overrides: Overrides {
+                log_level: matches.get_one::<String>("log-level").cloned(),
+                log_format: matches.get_one::<String>("log-format").map(|format| {
+                    match format.as_str() {
+                        "compact" => LogFormat::Compact,
+                        "pretty" => LogFormat::Pretty,
+                        _ => LogFormat::Full,
+                    }
+                }),
+                interfaces,
+            },
+            bus_name: matches
+                .get_one::<String>("bus-name")
+                .cloned()
+                .unwrap_or_else(|| DEFAULT_BUS_NAME.to_string()),
+            replace: matches.get_flag("replace"),
+            dry_run: matches.get_flag("dry-run"),
+            grants: matches.subcommand_matches("grants").and_then(|matches| {
+                match matches.subcommand() {
+                    Some(("list", _)) => Some(GrantsCommand::List),
+                    Some(("revoke", matches)) => matches
+                        .get_one::<String>("app-id")
+                        .map(|app_id| GrantsCommand::Revoke(app_id.clone())),
+                    _ => None,
+                }
+            }),
+        }
+    }
+}
+
+/// Prefix of the per-interface options, e.g. `remote-desktop` for `--remote-desktop-mode`.
+fn arg_name(interface: Interface) -> String {
+    interface.key().replace('_', "-")
+}
+
+/// Split `SERVICE[/OBJECT/PATH]`. Bus names never contain a slash, so the
+/// object path starts at the first one.
+fn split_destination(destination: &str) -> (String, Option<String>) {
+    match destination.find('/') {
+        Some(index) => (
+            destination[..index].to_string(),
+            Some(destination[index..].to_string()),
+        ),
+        None => (destination.to_string(), None),
+    }
+}
+
+fn command() -> Command {
+    let mut command = Command::new("xdg-desktop-portal-bypass")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("xdg-desktop-portal backend serving RemoteDesktop through uinput or other backends")
+        .arg(
+            Arg::new("config")
+                .short('c')
+                .long("config")
+                .value_name("PATH")
+                .value_parser(value_parser!(PathBuf))
+                .help("Config file, instead of $XDG_CONFIG_HOME and /etc"),
+        )
+        .arg(
+            Arg::new("bus-name")
+                .long("bus-name")
+                .value_name("NAME")
+                .help(format!(
+                    "Bus name to serve the portal under [default: {DEFAULT_BUS_NAME}]"
+                )),
+        )
+        .arg(
+            Arg::new("replace")
+                .short('r')
+                .long("replace")
+                .action(ArgAction::SetTrue)
+                .help("Replace a running instance owning the bus name"),
+        )
+        .arg(
+            Arg::new("log-level")
+                .short('l')
+                .long("log-level")
+                .value_name("FILTER")
+                .help("Log filter such as \"info\", overrides the config and RUST_LOG"),
+        )
+        .arg(
+            Arg::new("log-format")
+                .long("log-format")
+                .value_name("FORMAT")
+                .value_parser(["full", "compact", "pretty"])
+                .help("Log output format"),
+        )
+        .arg(
+            Arg::new("dry-run")
+                .short('n')
+                .long("dry-run")
+                .action(ArgAction::SetTrue)
+                .help("Validate the configuration, print it and exit"),
+        )
+        .subcommand(
+            Command::new("grants")
+                .about("List or revoke the RemoteDesktop grants of the permission store")
+                .subcommand_required(true)
+                .subcommand(Command::new("list").about("List the apps allowed without asking"))
+                .subcommand(
+                    Command::new("revoke")
+                        .about("Make an app ask again on its next session")
+                        .arg(Arg::new("app-id").value_name("APP_ID").required(true)),
+                ),
+        );
+
+    for interface in Interface::ALL {
+        let name = arg_name(interface);
+        let (mode, proxy) = (format!("{name}-mode"), format!("{name}-proxy"));
+        let interface = interface.name().rsplit('.').next().unwrap_or_default();
+        command = command
+            .arg(
+                Arg::new(mode.clone())
+                    .long(mode.clone())
+                    .value_name("MODE")
+                    .value_parser(["server", "proxy"])
+                    .help(format!("Working mode of the {interface} interface")),
+            )
+            .arg(
+                Arg::new(proxy.clone())
+                    .long(proxy)
+                    .value_name("SERVICE[/OBJECT/PATH]")
+                    .conflicts_with(mode)
+                    .help(format!("Proxy the {interface} interface to this backend")),
+            );
+    }
+
+    command
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Cli {
+        let args = ["xdg-desktop-portal-bypass"].iter().chain(args);
+        Cli::from_matches(&command().try_get_matches_from(args).unwrap())
+    }
+
+    #[test]
+    fn command_is_valid() {
+        command().debug_assert();
+    }
+
+    #[test]
+    fn defaults_keep_the_config() {
+        let cli = parse(&[]);
+        assert_eq!(cli.bus_name, DEFAULT_BUS_NAME);
+        assert!(!cli.replace && !cli.dry_run);
+        assert!(
+            cli.overrides
+                .interfaces
+                .values()
+                .all(|overrides| overrides.mode.is_none() && overrides.destination.is_none())
+        );
+        assert!(cli.overrides.log_level.is_none());
+        assert!(cli.grants.is_none());
+    }
+
+    #[test]
+    fn grants_subcommands() {
+        assert_eq!(parse(&["grants", "list"]).grants, Some(GrantsCommand::List));
+        assert_eq!(
+            parse(&["grants", "revoke", "org.example.Remote"]).grants,
+            Some(GrantsCommand::Revoke("org.example.Remote".to_string()))
+        );
+    }
+
+    #[test]
+    fn proxy_destination_is_split() {
+        let cli = parse(&[
+            "--screen-cast-proxy",
+            "org.freedesktop.impl.portal.desktop.wlr/org/example",
+            "--remote-desktop-mode",
+            "server",
+        ]);
+        let interfaces = &cli.overrides.interfaces;
+        assert_eq!(
+            interfaces[&Interface::ScreenCast].destination,
+            Some((
+                "org.freedesktop.impl.portal.desktop.wlr".to_string(),
+                Some("/org/example".to_string())
+            ))
+        );
+        assert_eq!(
+            interfaces[&Interface::RemoteDesktop].mode,
+            Some(Mode::Server)
+        );
+    }
+}
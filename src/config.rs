@@ -0,0 +1,626 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::Context;
+use serde::Deserialize;
+use tracing_subscriber::EnvFilter;
+use zbus::names::BusName;
+use zbus::zvariant;
+
+use crate::event_handler::policy::{Access, PolicyRule};
+use crate::event_handler::{
+    AuditOptions, ConsentMethod, ConsentOptions, DeviceOptions, Interface, ProxyDestination,
+    ProxyOptions, WorkingMode, XdgBypassConfig,
+};
+
+/// Directory of the configuration file below `$XDG_CONFIG_HOME` and `/etc`.
+const CONFIG_DIR: &str = "xdg-desktop-portal-bypass";
+const CONFIG_FILE: &str = "config.toml";
+/// Audit log below `$XDG_STATE_HOME`, unless `[audit] path` is set.
+const AUDIT_FILE: &str = "audit.jsonl";
+
+/// Object path portal backends are served at.
+const DEFAULT_OBJECT_PATH: &str = "/org/freedesktop/portal/desktop";
+
+/// Daemon configuration, as read from `config.toml`.
+#[derive(Debug)]
+pub struct Config {
+    /// File the configuration was read from, `None` when running on defaults.
+    pub path: Option<PathBuf>,
+    pub log: LogConfig,
+    pub xdg_bypass: XdgBypassConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// `tracing` filter directives, overridden by `RUST_LOG`.
+    pub level: String,
+    pub format: LogFormat,
+    /// Log the keys, positions and motion of input events instead of
+    /// redacting them. This logs everything typed in remote sessions.
+    pub input: bool,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            format: LogFormat::Full,
+            input: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Full,
+    Compact,
+    Pretty,
+}
+
+/// Layout of `config.toml`. Every section and key is optional.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ConfigFile {
+    log: LogConfig,
+    devices: DevicesConfig,
+    proxy: ProxyConfig,
+    consent: ConsentConfig,
+    /// `[[policy]]` rules of RemoteDesktop sessions, in order.
+    policy: Vec<PolicyConfig>,
+    audit: AuditConfig,
+    /// One section per portal interface, named after [`Interface::key`].
+    #[serde(flatten)]
+    interfaces: HashMap<String, InterfaceConfig>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct InterfaceConfig {
+    mode: Mode,
+    /// Bus name of the backend to proxy to, required by `mode = "proxy"`.
+    service_name: Option<String>,
+    /// Object path of the backend, defaults to `/org/freedesktop/portal/desktop`.
+    object_path: Option<String>,
+    /// Modes to fall back to, in order, when the proxy destination above
+    /// doesn't answer at session creation.
+    fallback: Vec<InterfaceConfig>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    #[default]
+    Server,
+    Proxy,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct DevicesConfig {
+    keyboard_layout: Option<String>,
+    keyboard_variant: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ProxyConfig {
+    /// Seconds to wait for the destination to answer a call, 0 waits forever.
+    timeout: u64,
+    /// Seconds to wait for `Start`, which usually waits for the user. 0 waits forever.
+    start_timeout: u64,
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        Self {
+            timeout: 10,
+            start_timeout: 0,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConsentConfig {
+    /// How `Start` of a local session is approved.
+    prompt: ConsentMethod,
+    /// Used when `prompt` can't be shown.
+    fallback: ConsentMethod,
+    /// Seconds the user has to answer, 0 waits forever.
+    timeout: u64,
+}
+
+impl Default for ConsentConfig {
+    fn default() -> Self {
+        let options = ConsentOptions::default();
+        Self {
+            prompt: options.prompt,
+            fallback: options.fallback,
+            timeout: options.timeout.map_or(0, |timeout| timeout.as_secs()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct AuditConfig {
+    enabled: bool,
+    /// Defaults to `$XDG_STATE_HOME/xdg-desktop-portal-bypass/audit.jsonl`.
+    path: Option<PathBuf>,
+    /// Record every keystroke, not only how many there were.
+    keystrokes: bool,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            path: None,
+            keystrokes: false,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PolicyConfig {
+    /// App id the rule applies to, a trailing `*` matches every app id starting with the rest.
+    app_id: String,
+    #[serde(default = "prompt")]
+    access: Access,
+    /// Device types the app may select, every type when unset.
+    device_types: Option<Vec<DeviceType>>,
+    /// Whether the app may select ScreenCast sources on its sessions.
+    #[serde(default = "yes")]
+    streams: bool,
+    /// Seconds after which sessions are closed, 0 keeps them open.
+    #[serde(default)]
+    max_duration: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum DeviceType {
+    Keyboard,
+    Pointer,
+    Touchscreen,
+}
+
+impl DeviceType {
+    fn bit(self) -> u32 {
+        match self {
+            DeviceType::Keyboard => 1,
+            DeviceType::Pointer => 2,
+            DeviceType::Touchscreen => 4,
+        }
+    }
+}
+
+fn prompt() -> Access {
+    Access::Prompt
+}
+
+fn yes() -> bool {
+    true
+}
+
+/// Values given on the command line, taking precedence over the file.
+#[derive(Debug, Clone, Default)]
+pub struct Overrides {
+    pub log_level: Option<String>,
+    pub log_format: Option<LogFormat>,
+    pub interfaces: HashMap<Interface, InterfaceOverride>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct InterfaceOverride {
+    pub mode: Option<Mode>,
+    /// Service name and optional object path to proxy to, implies proxy mode.
+    pub destination: Option<(String, Option<String>)>,
+}
+
+impl Config {
+    /// Read the configuration from `path`, or from the first file found in
+    /// `$XDG_CONFIG_HOME` and `/etc` when no path is given, then apply `overrides`.
+    ///
+    /// Without any file the defaults are used: every interface in server mode.
+    pub fn load(path: Option<&Path>, overrides: &Overrides) -> anyhow::Result<Self> {
+        let path = match path {
+            Some(path) => Some(path.to_path_buf()),
+            None => search_paths().into_iter().find(|path| path.is_file()),
+        };
+
+        let file = match &path {
+            Some(path) => {
+                let content = fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read config file {}", path.display()))?;
+                Self::parse(&content)
+                    .with_context(|| format!("Invalid config file {}", path.display()))?
+            }
+            None => ConfigFile::default(),
+        };
+
+        Self::from_file(path, file.with_overrides(overrides))
+    }
+
+    fn parse(content: &str) -> anyhow::Result<ConfigFile> {
+        Ok(toml::from_str(content)?)
+    }
+
+    fn from_file(path: Option<PathBuf>, file: ConfigFile) -> anyhow::Result<Self> {
+        EnvFilter::try_new(&file.log.level)
+            .with_context(|| format!("[log] Invalid level \"{}\"", file.log.level))?;
+
+        let mut interfaces = HashMap::new();
+        for (key, interface_config) in &file.interfaces {
+            let interface = Interface::ALL
+                .into_iter()
+                .find(|interface| interface.key() == key)
+                .with_context(|| format!("Unknown section [{}]", key))?;
+            let modes = interface_config
+                .working_modes()
+                .with_context(|| format!("[{}] Invalid working mode", key))?;
+            interfaces.insert(interface, modes);
+        }
+
+        let mut policy = Vec::new();
+        for (index, rule) in file.policy.into_iter().enumerate() {
+            policy.push(
+                rule.policy_rule()
+                    .with_context(|| format!("[[policy]] Invalid rule {}", index + 1))?,
+            );
+        }
+
+        let audit_path = match (file.audit.enabled, file.audit.path) {
+            (false, _) => None,
+            (true, Some(path)) => Some(path),
+            // Warned about once logging is set up, see `Audit::configure`.
+            (true, None) => default_audit_path(),
+        };
+
+        Ok(Self {
+            path,
+            log: file.log,
+            xdg_bypass: XdgBypassConfig {
+                interfaces,
+                devices: DeviceOptions {
+                    keyboard_layout: file.devices.keyboard_layout,
+                    keyboard_variant: file.devices.keyboard_variant,
+                },
+                proxy: ProxyOptions {
+                    timeout: seconds(file.proxy.timeout),
+                    start_timeout: seconds(file.proxy.start_timeout),
+                },
+                consent: ConsentOptions {
+                    prompt: file.consent.prompt,
+                    fallback: file.consent.fallback,
+                    timeout: seconds(file.consent.timeout),
+                },
+                policy,
+                audit: AuditOptions {
+                    enabled: file.audit.enabled,
+                    path: audit_path,
+                    keystrokes: file.audit.keystrokes,
+                },
+            },
+        })
+    }
+}
+
+impl ConfigFile {
+    fn with_overrides(mut self, overrides: &Overrides) -> Self {
+        if let Some(level) = &overrides.log_level {
+            self.log.level = level.clone();
+        }
+        if let Some(format) = overrides.log_format {
+            self.log.format = format;
+        }
+        for (interface, interface_overrides) in &overrides.interfaces {
+            self.interfaces
+                .entry(interface.key().to_string())
+                .or_default()
+                .apply(interface_overrides);
+        }
+        self
+    }
+}
+
+impl InterfaceConfig {
+    fn apply(&mut self, overrides: &InterfaceOverride) {
+        if let Some((service_name, object_path)) = &overrides.destination {
+            self.mode = Mode::Proxy;
+            self.service_name = Some(service_name.clone());
+            self.object_path = object_path.clone();
+        }
+        if let Some(mode) = overrides.mode {
+            self.mode = mode;
+            if mode == Mode::Server {
+                self.service_name = None;
+                self.object_path = None;
+                self.fallback.clear();
+            }
+        }
+    }
+
+    /// The working mode followed by its fallbacks.
+    fn working_modes(&self) -> anyhow::Result<Vec<WorkingMode>> {
+        let mut modes = vec![self.working_mode()?];
+        for (index, fallback) in self.fallback.iter().enumerate() {
+            if !fallback.fallback.is_empty() {
+                anyhow::bail!("fallback {} has a fallback of its own", index + 1);
+            }
+            if matches!(modes.last(), Some(WorkingMode::Server)) {
+                anyhow::bail!("mode = \"server\" is always available, nothing can follow it");
+            }
+            modes.push(
+                fallback
+                    .working_mode()
+                    .with_context(|| format!("Invalid fallback {}", index + 1))?,
+            );
+        }
+        Ok(modes)
+    }
+
+    fn working_mode(&self) -> anyhow::Result<WorkingMode> {
+        match self.mode {
+            Mode::Server => {
+                if self.service_name.is_some() || self.object_path.is_some() {
+                    anyhow::bail!("service_name and object_path are only used by mode = \"proxy\"");
+                }
+                Ok(WorkingMode::Server)
+            }
+            Mode::Proxy => {
+                let Some(service_name) = &self.service_name else {
+                    anyhow::bail!("mode = \"proxy\" requires a service_name");
+                };
+                BusName::try_from(service_name.as_str())
+                    .with_context(|| format!("\"{}\" is not a valid bus name", service_name))?;
+
+                let object_path = self.object_path.as_deref().unwrap_or(DEFAULT_OBJECT_PATH);
+                let object_path = zvariant::OwnedObjectPath::try_from(object_path)
+                    .with_context(|| format!("\"{}\" is not a valid object path", object_path))?;
+
+                Ok(WorkingMode::Proxy(ProxyDestination::new(
+                    service_name.clone(),
+                    object_path,
+                )))
+            }
+        }
+    }
+}
+
+impl PolicyConfig {
+    fn policy_rule(self) -> anyhow::Result<PolicyRule> {
+        if self.app_id.is_empty() {
+            anyhow::bail!("app_id is empty, use \"*\" to match every app");
+        }
+        let device_types = match &self.device_types {
+            Some(device_types) => device_types
+                .iter()
+                .fold(0, |types, device_type| types | device_type.bit()),
+            None => PolicyRule::default().device_types,
+        };
+        if device_types == 0 && self.access != Access::Deny {
+            anyhow::bail!("device_types is empty, use access = \"deny\" instead");
+        }
+        Ok(PolicyRule {
+            app_id: self.app_id,
+            access: self.access,
+            device_types,
+            streams: self.streams,
+            max_duration: seconds(self.max_duration),
+        })
+    }
+}
+
+/// `None` for 0, which disables the limit.
+fn seconds(seconds: u64) -> Option<Duration> {
+    (seconds != 0).then(|| Duration::from_secs(seconds))
+}
+
+/// Candidate config files, most specific first.
+fn search_paths() -> Vec<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+
+    config_home
+        .into_iter()
+        .chain([PathBuf::from("/etc")])
+        .map(|dir| dir.join(CONFIG_DIR).join(CONFIG_FILE))
+        .collect()
+}
+
+/// Audit log in the state directory of the user.
+fn default_audit_path() -> Option<PathBuf> {
+    env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state")))
+        .map(|dir| dir.join(CONFIG_DIR).join(AUDIT_FILE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(content: &str) -> anyhow::Result<Config> {
+        Config::from_file(None, Config::parse(content)?)
+    }
+
+    #[test]
+    fn empty_file_is_server_mode() {
+        let config = load("").unwrap();
+        for interface in Interface::ALL {
+            assert!(matches!(
+                config.xdg_bypass.mode(interface),
+                WorkingMode::Server
+            ));
+        }
+        assert_eq!(config.log.level, "info");
+        assert_eq!(config.log.format, LogFormat::Full);
+        assert!(!config.log.input);
+    }
+
+    #[test]
+    fn proxy_mode_reads_destination() {
+        let config = load(
+            r#"
+            [screen_cast]
+            mode = "proxy"
+            service_name = "org.freedesktop.impl.portal.desktop.wlr"
+
+            [devices]
+            keyboard_layout = "de"
+
+            [proxy]
+            start_timeout = 120
+
+            [consent]
+            prompt = "terminal"
+            fallback = "approve"
+            "#,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            config.xdg_bypass.mode(Interface::RemoteDesktop),
+            WorkingMode::Server
+        ));
+        let WorkingMode::Proxy(destination) = config.xdg_bypass.mode(Interface::ScreenCast) else {
+            panic!("screen_cast should be proxied");
+        };
+        assert_eq!(
+            destination.service_name(),
+            "org.freedesktop.impl.portal.desktop.wlr"
+        );
+        assert_eq!(destination.object_path().as_str(), DEFAULT_OBJECT_PATH);
+        assert_eq!(
+            config.xdg_bypass.devices.keyboard_layout.as_deref(),
+            Some("de")
+        );
+        assert_eq!(
+            config.xdg_bypass.proxy.timeout,
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(
+            config.xdg_bypass.proxy.start_timeout,
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(config.xdg_bypass.consent.prompt, ConsentMethod::Terminal);
+        assert_eq!(config.xdg_bypass.consent.fallback, ConsentMethod::Approve);
+        assert_eq!(
+            config.xdg_bypass.consent.timeout,
+            Some(Duration::from_secs(60))
+        );
+    }
+
+    #[test]
+    fn fallbacks_follow_the_mode() {
+        let config = load(
+            r#"
+            [remote_desktop]
+            mode = "proxy"
+            service_name = "org.freedesktop.impl.portal.desktop.wlr"
+
+            [[remote_desktop.fallback]]
+            mode = "proxy"
+            service_name = "org.freedesktop.impl.portal.desktop.kde"
+
+            [[remote_desktop.fallback]]
+            mode = "server"
+            "#,
+        )
+        .unwrap();
+
+        let modes = config.xdg_bypass.modes(Interface::RemoteDesktop);
+        assert_eq!(modes.len(), 3);
+        assert!(matches!(modes[2], WorkingMode::Server));
+        let destinations: Vec<&str> = config
+            .xdg_bypass
+            .proxy_destinations(Interface::RemoteDesktop)
+            .map(|destination| destination.service_name())
+            .collect();
+        assert_eq!(
+            destinations,
+            [
+                "org.freedesktop.impl.portal.desktop.wlr",
+                "org.freedesktop.impl.portal.desktop.kde"
+            ]
+        );
+        assert_eq!(config.xdg_bypass.modes(Interface::ScreenCast).len(), 1);
+
+        assert!(load("[remote_desktop]\n[[remote_desktop.fallback]]\nmode = \"server\"").is_err());
+    }
+
+    #[test]
+    fn policy_rules_keep_their_order() {
+        let config = load(
+            r#"
+            [[policy]]
+            app_id = "org.example.Automation"
+            access = "allow"
+            device_types = ["keyboard", "pointer"]
+            streams = false
+            max_duration = 3600
+
+            [[policy]]
+            app_id = "*"
+            access = "deny"
+            "#,
+        )
+        .unwrap();
+
+        let rule = config.xdg_bypass.policy_rule("org.example.Automation");
+        assert_eq!(rule.access, Access::Allow);
+        assert_eq!(rule.device_types, 3);
+        assert!(!rule.streams);
+        assert_eq!(rule.max_duration, Some(Duration::from_secs(3600)));
+        assert_eq!(
+            config.xdg_bypass.policy_rule("org.example.Other").access,
+            Access::Deny
+        );
+        assert_eq!(
+            load("")
+                .unwrap()
+                .xdg_bypass
+                .policy_rule("org.example.Other"),
+            PolicyRule::default()
+        );
+
+        assert!(load("[[policy]]\naccess = \"allow\"").is_err());
+        assert!(load("[[policy]]\napp_id = \"*\"\ndevice_types = [\"tablet\"]").is_err());
+        assert!(load("[[policy]]\napp_id = \"*\"\ndevice_types = []").is_err());
+    }
+
+    #[test]
+    fn audit_log_can_be_disabled() {
+        let config = load("[audit]\nenabled = false\nkeystrokes = true").unwrap();
+        assert_eq!(config.xdg_bypass.audit.path, None);
+
+        let config = load("[audit]\npath = \"/var/log/bypass.jsonl\"").unwrap();
+        assert_eq!(
+            config.xdg_bypass.audit.path.as_deref(),
+            Some(Path::new("/var/log/bypass.jsonl"))
+        );
+        assert!(!config.xdg_bypass.audit.keystrokes);
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        assert!(load("[remote_desktop]\nmode = \"proxy\"").is_err());
+        assert!(load("[remote_desktop]\nmode = \"mirror\"").is_err());
+        assert!(load("[remote_desktop]\nmode = \"proxy\"\nservice_name = \"not a name\"").is_err());
+        assert!(load("[log]\nformat = \"xml\"").is_err());
+        assert!(load("[consent]\nprompt = \"dialog\"").is_err());
+        assert!(load("[devices]\nkeyboard = \"us\"").is_err());
+        assert!(load("[screenshot]\nmode = \"server\"").is_err());
+    }
+}
@@ -1,32 +1,266 @@
 use crate::{
     dbus_listener::remote_desktop_listener::RemoteDesktopListener,
-    event_handler::{Event, EventHandle},
+    dbus_listener::screen_cast_listener::ScreenCastListener,
+    dbus_listener::session_listener::SessionListener,
+    event_handler::{Event, EventHandle, EventResponse},
 };
-use calloop::channel;
-use tracing::error;
-use zbus::blocking::Connection;
+use anyhow::Context;
+use calloop::{LoopSignal, channel};
+use futures::channel::oneshot;
+use std::collections::HashMap;
+use tracing::{debug, error, info};
+use zbus::ObjectServer;
+use zbus::blocking::connection::Builder;
+use zbus::blocking::{Connection, fdo};
+use zbus::fdo::{RequestNameFlags, RequestNameReply};
+use zbus::zvariant;
+
+use request_listener::RequestListener;
 
 mod remote_desktop_listener;
+mod request_listener;
 mod screen_cast_listener;
+mod session_listener;
+
+pub use session_listener::close_session;
+
+/// Object path every portal backend interface is served at.
+const PORTAL_OBJECT_PATH: &str = "/org/freedesktop/portal/desktop";
+
+/// Well-known name the portal backend is served under by default.
+pub const DEFAULT_BUS_NAME: &str = "org.freedesktop.impl.portal.desktop.bypass";
+
+/// How the listeners claim their bus name.
+pub struct ListenerOptions {
+    pub bus_name: String,
+    /// Take the name over from the current owner instead of failing.
+    pub replace: bool,
+    /// Also serve ScreenCast, which is only implemented by proxying.
+    pub screen_cast: bool,
+}
 
 pub struct DBusListener {
-    pub remote_desktop: Option<Connection>,
+    /// Connection every portal interface is served on.
+    pub connection: Option<Connection>,
 }
 
 impl DBusListener {
-    pub fn new(channel: channel::Sender<EventHandle>) -> Self {
-        let remote_desktop = RemoteDesktopListener::new(channel);
-        let remote_desktop = remote_desktop
-            .start()
+    pub fn new(channel: channel::Sender<EventHandle>, options: &ListenerOptions) -> Self {
+        let connection = Self::start(channel, options)
             .map_err(|e| {
-                error!("Can't start remote desktop listener: {:#?}", e);
+                error!("Can't start portal listener: {:#}", e);
             })
             .ok();
 
-        Self { remote_desktop }
+        Self { connection }
+    }
+
+    /// Serve the interfaces on one connection, so a single `.portal` entry
+    /// covers all of them.
+    fn start(
+        channel: channel::Sender<EventHandle>,
+        options: &ListenerOptions,
+    ) -> anyhow::Result<Connection> {
+        debug!("Starting portal listener as {}", options.bus_name);
+
+        let mut builder = Builder::session()?.serve_at(
+            PORTAL_OBJECT_PATH,
+            RemoteDesktopListener::new(channel.clone()),
+        )?;
+        if options.screen_cast {
+            builder = builder.serve_at(PORTAL_OBJECT_PATH, ScreenCastListener::new(channel))?;
+        }
+        let connection = builder
+            .build()
+            .with_context(|| "Failed to start portal listener")?;
+        request_name(&connection, options)?;
+
+        Ok(connection)
+    }
+
+    /// Stop the event loop once another instance started with `--replace`
+    /// takes the bus name over.
+    pub fn stop_on_name_lost(&self, bus_name: &str, stop_signal: LoopSignal) {
+        let Some(connection) = self.connection.clone() else {
+            return;
+        };
+        let bus_name = bus_name.to_string();
+
+        std::thread::spawn(move || {
+            let name_lost = fdo::DBusProxy::new(&connection)
+                .and_then(|proxy| proxy.receive_name_lost_with_args(&[(0, bus_name.as_str())]));
+            let name_lost = match name_lost {
+                Ok(name_lost) => name_lost,
+                Err(e) => {
+                    error!(
+                        "[DBusListener] Failed to watch bus name {}: {}",
+                        bus_name, e
+                    );
+                    return;
+                }
+            };
+
+            if name_lost.into_iter().next().is_some() {
+                info!(
+                    "[DBusListener] Bus name {} was taken over, stopping",
+                    bus_name
+                );
+                stop_signal.stop();
+                stop_signal.wakeup();
+            }
+        });
+    }
+}
+
+/// Claim `options.bus_name` on `connection`, allowing a later `--replace` to take it over.
+fn request_name(connection: &Connection, options: &ListenerOptions) -> anyhow::Result<()> {
+    let mut flags = RequestNameFlags::AllowReplacement | RequestNameFlags::DoNotQueue;
+    if options.replace {
+        flags |= RequestNameFlags::ReplaceExisting;
+    }
+
+    let taken = || {
+        format!(
+            "Bus name {} is owned by another process, start with --replace to take it over",
+            options.bus_name
+        )
+    };
+    match connection
+        .request_name_with_flags(options.bus_name.as_str(), flags)
+        .with_context(taken)?
+    {
+        RequestNameReply::PrimaryOwner | RequestNameReply::AlreadyOwner => Ok(()),
+        RequestNameReply::InQueue | RequestNameReply::Exists => Err(anyhow::anyhow!(taken())),
     }
 }
 
-pub trait Start {
-    fn start(self) -> anyhow::Result<Connection>;
+/// Send an event to the event loop and wait for the handler to answer it.
+///
+/// Returns `None` when the event could not be delivered or the handler dropped
+/// the response channel without answering.
+async fn dispatch(
+    sender: &channel::Sender<EventHandle>,
+    session: zvariant::OwnedObjectPath,
+    event: Event,
+) -> Option<EventResponse> {
+    let (return_tx, return_rx) = oneshot::channel::<EventResponse>();
+
+    if let Err(e) = sender.send(EventHandle {
+        session,
+        event,
+        return_tx,
+    }) {
+        error!("[DBusListener] Failed to send event to event loop: {e}");
+        return None;
+    }
+
+    return_rx.await.ok()
+}
+
+/// Like [`dispatch`], but exports a `Request` object at `handle` while the
+/// call is pending so the frontend can cancel it.
+async fn dispatch_with_request(
+    server: &ObjectServer,
+    sender: &channel::Sender<EventHandle>,
+    handle: zvariant::OwnedObjectPath,
+    session: zvariant::OwnedObjectPath,
+    event: Event,
+) -> Option<EventResponse> {
+    let request = RequestListener::new(sender.clone(), handle.clone(), session.clone());
+    if let Err(e) = server.at(&handle, request).await {
+        error!(
+            "[DBusListener] Failed to export request object {}: {}",
+            handle, e
+        );
+        return None;
+    }
+
+    let response = dispatch(sender, session, event).await;
+
+    // Already gone if the frontend closed the request.
+    let _ = server.remove::<RequestListener, _>(&handle).await;
+
+    response
+}
+
+/// Dispatch a request style method and turn the handler answer into the
+/// `(u32, a{sv})` pair expected by xdg-desktop-portal.
+async fn dispatch_request(
+    server: &ObjectServer,
+    sender: &channel::Sender<EventHandle>,
+    handle: zvariant::OwnedObjectPath,
+    session: zvariant::OwnedObjectPath,
+    event: Event,
+    module: &str,
+) -> (u32, HashMap<String, zvariant::OwnedValue>) {
+    match dispatch_with_request(server, sender, handle, session, event).await {
+        Some(EventResponse::Standard(response, results)) => {
+            let results = HashMap::<String, zvariant::OwnedValue>::try_from(results)
+                .unwrap_or_else(|_| {
+                    debug!("[{}] Results is not a dictionary, replying empty", module);
+                    HashMap::new()
+                });
+            (response, results)
+        }
+        Some(EventResponse::Value(_) | EventResponse::Fd(_)) => {
+            error!("[{}] Handler answered with a bare value", module);
+            (2, HashMap::new())
+        }
+        None => {
+            error!("[{}] Handler dropped the request without answering", module);
+            (2, HashMap::new())
+        }
+    }
+}
+
+/// Dispatch `CreateSession` and export the `Session` object once the handler
+/// accepted it.
+async fn create_session(
+    server: &ObjectServer,
+    sender: &channel::Sender<EventHandle>,
+    handle: zvariant::ObjectPath<'_>,
+    session_handle: zvariant::ObjectPath<'_>,
+    event: Event,
+    module: &str,
+) -> (u32, HashMap<String, zvariant::OwnedValue>) {
+    let (response, results) = dispatch_request(
+        server,
+        sender,
+        handle.into(),
+        session_handle.clone().into(),
+        event,
+        module,
+    )
+    .await;
+
+    if response == 0 {
+        let session = SessionListener::new(sender.clone(), session_handle.clone().into());
+        if let Err(e) = server.at(&session_handle, session).await {
+            error!(
+                "[{}] Failed to export session object {}: {}",
+                module, session_handle, e
+            );
+            // The frontend won't know the session, so nothing would close it.
+            dispatch(sender, session_handle.into(), Event::Close).await;
+            return (2, HashMap::new());
+        }
+    }
+
+    (response, results)
+}
+
+/// Dispatch a property getter and unwrap the `u32` value.
+async fn dispatch_property(
+    sender: &channel::Sender<EventHandle>,
+    event: Event,
+    module: &str,
+) -> zbus::fdo::Result<u32> {
+    match dispatch(sender, zvariant::OwnedObjectPath::default(), event).await {
+        Some(EventResponse::Value(value)) => u32::try_from(value)
+            .map_err(|e| zbus::fdo::Error::Failed(format!("[{}] {}", module, e))),
+        _ => Err(zbus::fdo::Error::Failed(format!(
+            "[{}] Handler failed to return the property",
+            module
+        ))),
+    }
 }
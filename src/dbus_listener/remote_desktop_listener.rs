@@ -1,13 +1,19 @@
 use crate::dbus_listener::EventHandle;
-use crate::dbus_listener::Start;
-use crate::event_handler::Event;
-use anyhow::Context;
+use crate::dbus_listener::create_session;
+use crate::dbus_listener::dispatch;
+use crate::dbus_listener::dispatch_property;
+use crate::dbus_listener::dispatch_request;
+use crate::event_handler::events::remote_desktop::{
+    ConnectToEIS, NotifyKeyboardKeycode, NotifyKeyboardKeysym, NotifyPointerAxis,
+    NotifyPointerAxisDiscrete, NotifyPointerButton, NotifyPointerMotion,
+    NotifyPointerMotionAbsolute, NotifyTouchDown, NotifyTouchMotion, NotifyTouchUp,
+    RemoteDesktopEvent, SelectDevices,
+};
+use crate::event_handler::{CreateSession, Event, EventResponse, Interface};
 use calloop::channel;
-use futures::channel::oneshot;
 use std::collections::HashMap;
 use tracing::debug;
-use zbus::blocking::Connection;
-use zbus::blocking::connection::Builder;
+use zbus::ObjectServer;
 use zbus::interface;
 use zbus::zvariant;
 
@@ -19,336 +25,430 @@ impl RemoteDesktopListener {
     pub fn new(sender: channel::Sender<EventHandle>) -> Self {
         Self { sender }
     }
+
+    /// Dispatch a `Notify*` method. These have no results, a handler only
+    /// answers them to report a failure.
+    async fn dispatch_notify(
+        &self,
+        session: zvariant::OwnedObjectPath,
+        event: Event,
+        module: &str,
+    ) -> zbus::fdo::Result<()> {
+        match dispatch(&self.sender, session, event).await {
+            Some(EventResponse::Standard(response, _)) if response != 0 => Err(
+                zbus::fdo::Error::Failed(format!("[{}] Handler failed with {}", module, response)),
+            ),
+            _ => Ok(()),
+        }
+    }
 }
 
 #[interface(name = "org.freedesktop.impl.portal.RemoteDesktop")]
 impl RemoteDesktopListener {
     async fn create_session(
         &self,
+        #[zbus(object_server)] server: &ObjectServer,
         handle: zvariant::ObjectPath<'_>,
         session_handle: zvariant::ObjectPath<'_>,
         app_id: String,
         options: HashMap<String, zvariant::OwnedValue>,
-    ) -> zvariant::OwnedValue {
-        // let event = Event::RemoteDesktop(RemoteDesktopEvent::CreateSession(CreateSession {
-        //     handle: handle.into_owned(),
-        //     session_handle: session_handle.into_owned(),
-        //     app_id,
-        //     options,
-        // }));
-
-        // debug!(
-        //     "Interface called [RemoteDesktop.CreateSession] {:#?}",
-        //     event
-        // );
-
-        // let (tx, rx) = oneshot::channel::<zvariant::OwnedValue>();
-
-        // self.sender.send((event, tx));
-
-        // match rx.await {
-        //     Ok(value) => value,
-        //     Err(_) => zvariant::OwnedValue::from(2),
-        // }
-        zvariant::OwnedValue::from(0)
+    ) -> (u32, HashMap<String, zvariant::OwnedValue>) {
+        let event = Event::CreateSession(CreateSession {
+            interface: Interface::RemoteDesktop,
+            handle: handle.to_owned(),
+            app_id,
+            options,
+        });
+
+        debug!(
+            "Interface called [RemoteDesktop.CreateSession] {:#?}",
+            event
+        );
+
+        create_session(
+            server,
+            &self.sender,
+            handle,
+            session_handle,
+            event,
+            "RemoteDesktop.CreateSession",
+        )
+        .await
     }
 
     async fn select_devices(
         &self,
+        #[zbus(object_server)] server: &ObjectServer,
         handle: zvariant::ObjectPath<'_>,
         session_handle: zvariant::ObjectPath<'_>,
         app_id: String,
         options: HashMap<String, zvariant::OwnedValue>,
-    ) -> zvariant::OwnedValue {
-        // let event = Event::RemoteDesktop(RemoteDesktopEvent::SelectDevices(SelectDevices {
-        //     handle: handle.into_owned(),
-        //     session_handle: session_handle.into_owned(),
-        //     app_id,
-        //     options,
-        // }));
-
-        // debug!(
-        //     "Interface called [RemoteDesktop.SelectDevices] {:#?}",
-        //     event
-        // );
-
-        // let (tx, rx) = oneshot::channel::<zvariant::OwnedValue>();
-
-        // self.sender.send((event, tx));
-
-        // match rx.await {
-        //     Ok(value) => value,
-        //     Err(_) => zvariant::OwnedValue::from(2),
-        // }
-        zvariant::OwnedValue::from(0)
+    ) -> (u32, HashMap<String, zvariant::OwnedValue>) {
+        let event = Event::RemoteDesktop(RemoteDesktopEvent::SelectDevices(SelectDevices {
+            handle: handle.to_owned(),
+            app_id,
+            options,
+        }));
+
+        debug!(
+            "Interface called [RemoteDesktop.SelectDevices] {:#?}",
+            event
+        );
+
+        dispatch_request(
+            server,
+            &self.sender,
+            handle.into(),
+            session_handle.into(),
+            event,
+            "RemoteDesktop.SelectDevices",
+        )
+        .await
     }
 
     async fn start(
         &self,
+        #[zbus(object_server)] server: &ObjectServer,
         handle: zvariant::ObjectPath<'_>,
         session_handle: zvariant::ObjectPath<'_>,
         app_id: String,
         parent_window: String,
         options: HashMap<String, zvariant::OwnedValue>,
-    ) -> zvariant::OwnedValue {
-        // let event = Event::RemoteDesktop(RemoteDesktopEvent::Start(
-        //     crate::event_handler::remote_desktop::Start {
-        //         handle: handle.into_owned(),
-        //         session_handle: session_handle.into_owned(),
-        //         app_id,
-        //         parent_window,
-        //         options,
-        //     },
-        // ));
-
-        // debug!("Interface called [RemoteDesktop.Start] {:#?}", event);
-
-        // let (tx, rx) = oneshot::channel::<zvariant::OwnedValue>();
-
-        // self.sender.send((event, tx));
-
-        // match rx.await {
-        //     Ok(value) => value,
-        //     Err(_) => zvariant::OwnedValue::from(2),
-        // }
-        zvariant::OwnedValue::from(0)
+    ) -> (u32, HashMap<String, zvariant::OwnedValue>) {
+        let event = Event::RemoteDesktop(RemoteDesktopEvent::Start(
+            crate::event_handler::events::remote_desktop::Start {
+                handle: handle.to_owned(),
+                app_id,
+                parent_window,
+                options,
+            },
+        ));
+
+        debug!("Interface called [RemoteDesktop.Start] {:#?}", event);
+
+        dispatch_request(
+            server,
+            &self.sender,
+            handle.into(),
+            session_handle.into(),
+            event,
+            "RemoteDesktop.Start",
+        )
+        .await
     }
 
-    pub fn notify_pointer_motion(
+    async fn notify_pointer_motion(
         &self,
         session_handle: zvariant::ObjectPath<'_>,
         options: HashMap<String, zvariant::OwnedValue>,
         dx: f64,
         dy: f64,
-    ) {
-        // let event = Event::RemoteDesktop(RemoteDesktopEvent::NotifyPointerMotion(
-        //     NotifyPointerMotion {
-        //         session_handle: session_handle.into_owned(),
-        //         options,
-        //         dx,
-        //         dy,
-        //     },
-        // ));
-
-        // debug!(
-        //     "Interface called [RemoteDesktop.NotifyPointerMotion] {:#?}",
-        //     event
-        // );
-
-        // let (tx, _) = oneshot::channel::<zvariant::OwnedValue>();
-
-        // self.sender.send((event, tx));
+    ) -> zbus::fdo::Result<()> {
+        let event = Event::RemoteDesktop(RemoteDesktopEvent::NotifyPointerMotion(
+            NotifyPointerMotion { options, dx, dy },
+        ));
+
+        debug!(
+            "Interface called [RemoteDesktop.NotifyPointerMotion] {:#?}",
+            event
+        );
+
+        self.dispatch_notify(
+            session_handle.into(),
+            event,
+            "RemoteDesktop.NotifyPointerMotion",
+        )
+        .await
     }
 
-    pub fn notify_pointer_motion_absolute(
+    async fn notify_pointer_motion_absolute(
         &self,
         session_handle: zvariant::ObjectPath<'_>,
         options: HashMap<String, zvariant::OwnedValue>,
         stream: u32,
         x: f64,
         y: f64,
-    ) {
-        // let event = Event::RemoteDesktop(RemoteDesktopEvent::NotifyPointerMotionAbsolute(
-        //     NotifyPointerMotionAbsolute {
-        //         session_handle: session_handle.into_owned(),
-        //         options,
-        //         stream,
-        //         x,
-        //         y,
-        //     },
-        // ));
-
-        // debug!(
-        //     "Interface called [RemoteDesktop.NotifyPointerMotionAbsolute] {:#?}",
-        //     event
-        // );
-
-        // let (tx, _) = oneshot::channel::<zvariant::OwnedValue>();
-
-        // self.sender.send((event, tx));
+    ) -> zbus::fdo::Result<()> {
+        let event = Event::RemoteDesktop(RemoteDesktopEvent::NotifyPointerMotionAbsolute(
+            NotifyPointerMotionAbsolute {
+                options,
+                stream,
+                x,
+                y,
+            },
+        ));
+
+        debug!(
+            "Interface called [RemoteDesktop.NotifyPointerMotionAbsolute] {:#?}",
+            event
+        );
+
+        self.dispatch_notify(
+            session_handle.into(),
+            event,
+            "RemoteDesktop.NotifyPointerMotionAbsolute",
+        )
+        .await
     }
 
-    pub fn notify_pointer_button(
+    async fn notify_pointer_button(
         &self,
         session_handle: zvariant::ObjectPath<'_>,
         options: HashMap<String, zvariant::OwnedValue>,
         button: i32,
         state: u32,
-    ) {
-        // let event = Event::RemoteDesktop(RemoteDesktopEvent::NotifyPointerButton(
-        //     NotifyPointerButton {
-        //         session_handle: session_handle.into_owned(),
-        //         options,
-        //         button,
-        //         state,
-        //     },
-        // ));
-
-        // debug!(
-        //     "Interface called [RemoteDesktop.NotifyPointerButton] {:#?}",
-        //     event
-        // );
-
-        // let (tx, _) = oneshot::channel::<zvariant::OwnedValue>();
-
-        // self.sender.send((event, tx));
+    ) -> zbus::fdo::Result<()> {
+        let event = Event::RemoteDesktop(RemoteDesktopEvent::NotifyPointerButton(
+            NotifyPointerButton {
+                options,
+                button,
+                state,
+            },
+        ));
+
+        debug!(
+            "Interface called [RemoteDesktop.NotifyPointerButton] {:#?}",
+            event
+        );
+
+        self.dispatch_notify(
+            session_handle.into(),
+            event,
+            "RemoteDesktop.NotifyPointerButton",
+        )
+        .await
     }
 
-    pub fn notify_pointer_axis(
+    async fn notify_pointer_axis(
         &self,
         session_handle: zvariant::ObjectPath<'_>,
         options: HashMap<String, zvariant::OwnedValue>,
         dx: f64,
         dy: f64,
-    ) {
-        // let event =
-        //     Event::RemoteDesktop(RemoteDesktopEvent::NotifyPointerAxis(NotifyPointerAxis {
-        //         session_handle: session_handle.into_owned(),
-        //         options,
-        //         dx,
-        //         dy,
-        //     }));
-
-        // debug!(
-        //     "Interface called [RemoteDesktop.NotifyPointerAxis] {:#?}",
-        //     event
-        // );
-
-        // let (tx, _) = oneshot::channel::<zvariant::OwnedValue>();
-
-        // self.sender.send((event, tx));
+    ) -> zbus::fdo::Result<()> {
+        let event =
+            Event::RemoteDesktop(RemoteDesktopEvent::NotifyPointerAxis(NotifyPointerAxis {
+                options,
+                dx,
+                dy,
+            }));
+
+        debug!(
+            "Interface called [RemoteDesktop.NotifyPointerAxis] {:#?}",
+            event
+        );
+
+        self.dispatch_notify(
+            session_handle.into(),
+            event,
+            "RemoteDesktop.NotifyPointerAxis",
+        )
+        .await
     }
 
-    pub fn notify_pointer_axis_discrete(
+    async fn notify_pointer_axis_discrete(
         &self,
         session_handle: zvariant::ObjectPath<'_>,
         options: HashMap<String, zvariant::OwnedValue>,
         axis: u32,
         steps: i32,
-    ) {
-        // let event = Event::RemoteDesktop(RemoteDesktopEvent::NotifyPointerAxisDiscrete(
-        //     NotifyPointerAxisDiscrete {
-        //         session_handle: session_handle.into_owned(),
-        //         options,
-        //         axis,
-        //         steps,
-        //     },
-        // ));
-
-        // debug!(
-        //     "Interface called [RemoteDesktop.NotifyPointerAxisDiscrete] {:#?}",
-        //     event
-        // );
-
-        // let (tx, _) = oneshot::channel::<zvariant::OwnedValue>();
-
-        // self.sender.send((event, tx));
+    ) -> zbus::fdo::Result<()> {
+        let event = Event::RemoteDesktop(RemoteDesktopEvent::NotifyPointerAxisDiscrete(
+            NotifyPointerAxisDiscrete {
+                options,
+                axis,
+                steps,
+            },
+        ));
+
+        debug!(
+            "Interface called [RemoteDesktop.NotifyPointerAxisDiscrete] {:#?}",
+            event
+        );
+
+        self.dispatch_notify(
+            session_handle.into(),
+            event,
+            "RemoteDesktop.NotifyPointerAxisDiscrete",
+        )
+        .await
     }
 
-    pub fn notify_keyboard_keycode(
+    async fn notify_keyboard_keycode(
         &self,
         session_handle: zvariant::ObjectPath<'_>,
         options: HashMap<String, zvariant::OwnedValue>,
         keycode: i32,
         state: u32,
-    ) {
-        // let event = Event::RemoteDesktop(RemoteDesktopEvent::NotifyKeyboardKeycode(
-        //     NotifyKeyboardKeycode {
-        //         session_handle: session_handle.into_owned(),
-        //         options,
-        //         keycode,
-        //         state,
-        //     },
-        // ));
-
-        // debug!(
-        //     "Interface called [RemoteDesktop.NotifyKeyboardKeycode] {:#?}",
-        //     event
-        // );
-
-        // let (tx, _) = oneshot::channel::<zvariant::OwnedValue>();
-
-        // self.sender.send((event, tx));
+    ) -> zbus::fdo::Result<()> {
+        let event = Event::RemoteDesktop(RemoteDesktopEvent::NotifyKeyboardKeycode(
+            NotifyKeyboardKeycode {
+                options,
+                keycode,
+                state,
+            },
+        ));
+
+        debug!(
+            "Interface called [RemoteDesktop.NotifyKeyboardKeycode] {:#?}",
+            event
+        );
+
+        self.dispatch_notify(
+            session_handle.into(),
+            event,
+            "RemoteDesktop.NotifyKeyboardKeycode",
+        )
+        .await
     }
 
-    pub fn notify_keyboard_keysym(
+    async fn notify_keyboard_keysym(
         &self,
         session_handle: zvariant::ObjectPath<'_>,
         options: HashMap<String, zvariant::OwnedValue>,
         keysym: i32,
         state: u32,
-    ) {
-        // let event = Event::RemoteDesktop(RemoteDesktopEvent::NotifyKeyboardKeysym(
-        //     NotifyKeyboardKeysym {
-        //         session_handle: session_handle.into_owned(),
-        //         options,
-        //         keysym,
-        //         state,
-        //     },
-        // ));
-
-        // debug!(
-        //     "Interface called [RemoteDesktop.NotifyKeyboardKeysym] {:#?}",
-        //     event
-        // );
-
-        // let (tx, _) = oneshot::channel::<zvariant::OwnedValue>();
-
-        // self.sender.send((event, tx));
+    ) -> zbus::fdo::Result<()> {
+        let event = Event::RemoteDesktop(RemoteDesktopEvent::NotifyKeyboardKeysym(
+            NotifyKeyboardKeysym {
+                options,
+                keysym,
+                state,
+            },
+        ));
+
+        debug!(
+            "Interface called [RemoteDesktop.NotifyKeyboardKeysym] {:#?}",
+            event
+        );
+
+        self.dispatch_notify(
+            session_handle.into(),
+            event,
+            "RemoteDesktop.NotifyKeyboardKeysym",
+        )
+        .await
     }
 
-    /**
-     * considered that no many devices need touch api
-     * so if need we will add it later
-     */
+    async fn notify_touch_down(
+        &self,
+        session_handle: zvariant::ObjectPath<'_>,
+        options: HashMap<String, zvariant::OwnedValue>,
+        stream: u32,
+        slot: u32,
+        x: f64,
+        y: f64,
+    ) -> zbus::fdo::Result<()> {
+        let event = Event::RemoteDesktop(RemoteDesktopEvent::NotifyTouchDown(NotifyTouchDown {
+            options,
+            stream,
+            slot,
+            x,
+            y,
+        }));
+
+        debug!(
+            "Interface called [RemoteDesktop.NotifyTouchDown] {:#?}",
+            event
+        );
+
+        self.dispatch_notify(
+            session_handle.into(),
+            event,
+            "RemoteDesktop.NotifyTouchDown",
+        )
+        .await
+    }
 
-    #[zbus(property)]
-    async fn available_device_types(&self) -> zvariant::OwnedValue {
-        // let (tx, rx) = oneshot::channel::<zvariant::OwnedValue>();
-
-        // self.sender.send((
-        //     Event::RemoteDesktop(RemoteDesktopEvent::GetPropertiesAvilableDeviceTypes(
-        //         GetPropertiesAvilableDeviceTypes {},
-        //     )),
-        //     tx,
-        // ));
-
-        // match rx.await {
-        //     Ok(value) => value,
-        //     Err(_) => zvariant::OwnedValue::from(2),
-        // }
-        zvariant::OwnedValue::from(0)
+    async fn notify_touch_motion(
+        &self,
+        session_handle: zvariant::ObjectPath<'_>,
+        options: HashMap<String, zvariant::OwnedValue>,
+        stream: u32,
+        slot: u32,
+        x: f64,
+        y: f64,
+    ) -> zbus::fdo::Result<()> {
+        let event =
+            Event::RemoteDesktop(RemoteDesktopEvent::NotifyTouchMotion(NotifyTouchMotion {
+                options,
+                stream,
+                slot,
+                x,
+                y,
+            }));
+
+        debug!(
+            "Interface called [RemoteDesktop.NotifyTouchMotion] {:#?}",
+            event
+        );
+
+        self.dispatch_notify(
+            session_handle.into(),
+            event,
+            "RemoteDesktop.NotifyTouchMotion",
+        )
+        .await
     }
 
-    #[zbus(property)]
-    async fn version(&self) -> zvariant::OwnedValue {
-        // let (tx, rx) = oneshot::channel::<zvariant::OwnedValue>();
-
-        // self.sender.send((
-        //     Event::RemoteDesktop(RemoteDesktopEvent::GetPropertiesVersion(
-        //         GetPropertiesVersion {},
-        //     )),
-        //     tx,
-        // ));
-
-        // match rx.await {
-        //     Ok(value) => value,
-        //     Err(_) => zvariant::OwnedValue::from(2),
-        // }
-        zvariant::OwnedValue::from(0)
+    async fn notify_touch_up(
+        &self,
+        session_handle: zvariant::ObjectPath<'_>,
+        options: HashMap<String, zvariant::OwnedValue>,
+        slot: u32,
+    ) -> zbus::fdo::Result<()> {
+        let event = Event::RemoteDesktop(RemoteDesktopEvent::NotifyTouchUp(NotifyTouchUp {
+            options,
+            slot,
+        }));
+
+        debug!(
+            "Interface called [RemoteDesktop.NotifyTouchUp] {:#?}",
+            event
+        );
+
+        self.dispatch_notify(session_handle.into(), event, "RemoteDesktop.NotifyTouchUp")
+            .await
     }
-}
 
-impl Start for RemoteDesktopListener {
-    fn start(self) -> anyhow::Result<Connection> {
-        debug!("Starting RemoteDesktopListener");
+    async fn connect_to_eis(
+        &self,
+        session_handle: zvariant::ObjectPath<'_>,
+        app_id: String,
+        options: HashMap<String, zvariant::OwnedValue>,
+    ) -> zbus::fdo::Result<zvariant::OwnedFd> {
+        let event = Event::RemoteDesktop(RemoteDesktopEvent::ConnectToEIS(ConnectToEIS {
+            app_id,
+            options,
+        }));
+
+        debug!("Interface called [RemoteDesktop.ConnectToEIS] {:#?}", event);
+
+        match dispatch(&self.sender, session_handle.into(), event).await {
+            Some(EventResponse::Fd(fd)) => Ok(fd),
+            _ => Err(zbus::fdo::Error::Failed(
+                "[RemoteDesktop.ConnectToEIS] Handler failed to connect to EIS".to_string(),
+            )),
+        }
+    }
 
-        let connection = Builder::session()?
-            .name("org.freedesktop.impl.portal.desktop.bypass")?
-            .serve_at("/org/freedesktop/portal/desktop", self)?
-            .build()
-            .with_context(|| "Failed to start RemoteDesktopListener")?;
+    #[zbus(property)]
+    async fn available_device_types(&self) -> zbus::fdo::Result<u32> {
+        dispatch_property(
+            &self.sender,
+            Event::RemoteDesktop(RemoteDesktopEvent::GetPropertiesAvilableDeviceTypes),
+            "RemoteDesktop.AvailableDeviceTypes",
+        )
+        .await
+    }
 
-        Ok(connection)
+    #[zbus(property)]
+    async fn version(&self) -> zbus::fdo::Result<u32> {
+        dispatch_property(
+            &self.sender,
+            Event::RemoteDesktop(RemoteDesktopEvent::GetPropertiesVersion),
+            "RemoteDesktop.Version",
+        )
+        .await
     }
 }
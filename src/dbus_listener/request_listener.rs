@@ -0,0 +1,52 @@
+use calloop::channel;
+use tracing::{debug, error};
+use zbus::{ObjectServer, interface, zvariant};
+
+use crate::dbus_listener::dispatch;
+use crate::event_handler::{CloseRequest, Event, EventHandle};
+
+/// `org.freedesktop.impl.portal.Request` object exported at the request handle
+/// for as long as the method call it belongs to is pending.
+pub struct RequestListener {
+    sender: channel::Sender<EventHandle>,
+    handle: zvariant::OwnedObjectPath,
+    session_handle: zvariant::OwnedObjectPath,
+}
+
+impl RequestListener {
+    pub fn new(
+        sender: channel::Sender<EventHandle>,
+        handle: zvariant::OwnedObjectPath,
+        session_handle: zvariant::OwnedObjectPath,
+    ) -> Self {
+        Self {
+            sender,
+            handle,
+            session_handle,
+        }
+    }
+}
+
+#[interface(name = "org.freedesktop.impl.portal.Request")]
+impl RequestListener {
+    async fn close(&self, #[zbus(object_server)] server: &ObjectServer) {
+        debug!("Interface called [Request.Close] {}", self.handle);
+
+        // The handler answers the pending call itself with response code 1.
+        dispatch(
+            &self.sender,
+            self.session_handle.clone(),
+            Event::CloseRequest(CloseRequest {
+                handle: self.handle.clone().into_inner(),
+            }),
+        )
+        .await;
+
+        if let Err(e) = server.remove::<RequestListener, _>(&self.handle).await {
+            error!(
+                "[Request.Close] Failed to remove request object {}: {}",
+                self.handle, e
+            );
+        }
+    }
+}
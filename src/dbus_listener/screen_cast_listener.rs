@@ -1,31 +1,169 @@
-// use anyhow::Context;
-// use calloop::channel;
-// use zbus::blocking::Connection;
-
-// use crate::dbus_listener::WorkingMode;
-// use crate::event_handler::Event;
-
-// pub struct ScreenCastListener {
-//     working_mode: WorkingMode,
-
-//     sender: Option<channel::Sender<Event>>,
-
-//     proxy_client: Option<Connection>,
-// }
-
-// impl ScreenCastListener {
-//     pub fn new(working_mode: WorkingMode, sender: Option<channel::Sender<Event>>) -> Self {
-//         match working_mode {
-//             WorkingMode::Server => Self {
-//                 working_mode,
-//                 sender: sender
-//                     .with_context(|| "No sender provided in server mode")
-//                     .ok(),
-//             },
-//             WorkingMode::Proxy => Self {
-//                 working_mode,
-//                 sender: None,
-//             },
-//         }
-//     }
-// }
+use crate::dbus_listener::EventHandle;
+use crate::dbus_listener::create_session;
+use crate::dbus_listener::dispatch;
+use crate::dbus_listener::dispatch_property;
+use crate::dbus_listener::dispatch_request;
+use crate::event_handler::events::screen_cast::{
+    OpenPipeWireRemote, ScreenCastEvent, SelectSources,
+};
+use crate::event_handler::{CreateSession, Event, EventResponse, Interface};
+use calloop::channel;
+use std::collections::HashMap;
+use tracing::debug;
+use zbus::ObjectServer;
+use zbus::interface;
+use zbus::zvariant;
+
+/// ScreenCast is never implemented locally, every call ends up at the
+/// configured proxy destination. The listener is only exported in proxy mode.
+pub struct ScreenCastListener {
+    sender: channel::Sender<EventHandle>,
+}
+
+impl ScreenCastListener {
+    pub fn new(sender: channel::Sender<EventHandle>) -> Self {
+        Self { sender }
+    }
+}
+
+#[interface(name = "org.freedesktop.impl.portal.ScreenCast")]
+impl ScreenCastListener {
+    async fn create_session(
+        &self,
+        #[zbus(object_server)] server: &ObjectServer,
+        handle: zvariant::ObjectPath<'_>,
+        session_handle: zvariant::ObjectPath<'_>,
+        app_id: String,
+        options: HashMap<String, zvariant::OwnedValue>,
+    ) -> (u32, HashMap<String, zvariant::OwnedValue>) {
+        let event = Event::CreateSession(CreateSession {
+            interface: Interface::ScreenCast,
+            handle: handle.to_owned(),
+            app_id,
+            options,
+        });
+
+        debug!("Interface called [ScreenCast.CreateSession] {:#?}", event);
+
+        create_session(
+            server,
+            &self.sender,
+            handle,
+            session_handle,
+            event,
+            "ScreenCast.CreateSession",
+        )
+        .await
+    }
+
+    async fn select_sources(
+        &self,
+        #[zbus(object_server)] server: &ObjectServer,
+        handle: zvariant::ObjectPath<'_>,
+        session_handle: zvariant::ObjectPath<'_>,
+        app_id: String,
+        options: HashMap<String, zvariant::OwnedValue>,
+    ) -> (u32, HashMap<String, zvariant::OwnedValue>) {
+        let event = Event::ScreenCast(ScreenCastEvent::SelectSources(SelectSources {
+            handle: handle.to_owned(),
+            app_id,
+            options,
+        }));
+
+        debug!("Interface called [ScreenCast.SelectSources] {:#?}", event);
+
+        dispatch_request(
+            server,
+            &self.sender,
+            handle.into(),
+            session_handle.into(),
+            event,
+            "ScreenCast.SelectSources",
+        )
+        .await
+    }
+
+    async fn start(
+        &self,
+        #[zbus(object_server)] server: &ObjectServer,
+        handle: zvariant::ObjectPath<'_>,
+        session_handle: zvariant::ObjectPath<'_>,
+        app_id: String,
+        parent_window: String,
+        options: HashMap<String, zvariant::OwnedValue>,
+    ) -> (u32, HashMap<String, zvariant::OwnedValue>) {
+        let event = Event::ScreenCast(ScreenCastEvent::Start(
+            crate::event_handler::events::screen_cast::Start {
+                handle: handle.to_owned(),
+                app_id,
+                parent_window,
+                options,
+            },
+        ));
+
+        debug!("Interface called [ScreenCast.Start] {:#?}", event);
+
+        dispatch_request(
+            server,
+            &self.sender,
+            handle.into(),
+            session_handle.into(),
+            event,
+            "ScreenCast.Start",
+        )
+        .await
+    }
+
+    async fn open_pipe_wire_remote(
+        &self,
+        session_handle: zvariant::ObjectPath<'_>,
+        options: HashMap<String, zvariant::OwnedValue>,
+    ) -> zbus::fdo::Result<zvariant::OwnedFd> {
+        let event = Event::ScreenCast(ScreenCastEvent::OpenPipeWireRemote(OpenPipeWireRemote {
+            options,
+        }));
+
+        debug!(
+            "Interface called [ScreenCast.OpenPipeWireRemote] {:#?}",
+            event
+        );
+
+        match dispatch(&self.sender, session_handle.into(), event).await {
+            Some(EventResponse::Fd(fd)) => Ok(fd),
+            _ => Err(zbus::fdo::Error::Failed(
+                "[ScreenCast.OpenPipeWireRemote] Handler failed to open a PipeWire remote"
+                    .to_string(),
+            )),
+        }
+    }
+
+    #[zbus(property)]
+    async fn available_source_types(&self) -> zbus::fdo::Result<u32> {
+        dispatch_property(
+            &self.sender,
+            Event::ScreenCast(ScreenCastEvent::GetPropertiesAvailableSourceTypes),
+            "ScreenCast.AvailableSourceTypes",
+        )
+        .await
+    }
+
+    #[zbus(property)]
+    async fn available_cursor_modes(&self) -> zbus::fdo::Result<u32> {
+        dispatch_property(
+            &self.sender,
+            Event::ScreenCast(ScreenCastEvent::GetPropertiesAvailableCursorModes),
+            "ScreenCast.AvailableCursorModes",
+        )
+        .await
+    }
+
+    #[zbus(property)]
+    async fn version(&self) -> zbus::fdo::Result<u32> {
+        dispatch_property(
+            &self.sender,
+            Event::ScreenCast(ScreenCastEvent::GetPropertiesVersion),
+            "ScreenCast.Version",
+        )
+        .await
+    }
+}
@@ -0,0 +1,73 @@
+use calloop::channel;
+use tracing::{debug, error};
+use zbus::object_server::SignalEmitter;
+use zbus::{ObjectServer, interface, zvariant};
+
+use crate::dbus_listener::dispatch;
+use crate::event_handler::{Event, EventHandle};
+
+/// `org.freedesktop.impl.portal.Session` object exported at every session handle
+/// the frontend passed to `CreateSession`.
+pub struct SessionListener {
+    sender: channel::Sender<EventHandle>,
+    session_handle: zvariant::OwnedObjectPath,
+}
+
+impl SessionListener {
+    pub fn new(
+        sender: channel::Sender<EventHandle>,
+        session_handle: zvariant::OwnedObjectPath,
+    ) -> Self {
+        Self {
+            sender,
+            session_handle,
+        }
+    }
+}
+
+#[interface(name = "org.freedesktop.impl.portal.Session")]
+impl SessionListener {
+    async fn close(&self, #[zbus(object_server)] server: &ObjectServer) {
+        debug!("Interface called [Session.Close] {}", self.session_handle);
+
+        // Wait for the handler to tear the session down before the object goes away.
+        dispatch(&self.sender, self.session_handle.clone(), Event::Close).await;
+
+        if let Err(e) = server
+            .remove::<SessionListener, _>(&self.session_handle)
+            .await
+        {
+            error!(
+                "[Session.Close] Failed to remove session object {}: {}",
+                self.session_handle, e
+            );
+        }
+    }
+
+    #[zbus(signal)]
+    async fn closed(emitter: &SignalEmitter<'_>) -> zbus::Result<()>;
+
+    #[zbus(property(emits_changed_signal = "const"), name = "version")]
+    fn version(&self) -> u32 {
+        1
+    }
+}
+
+/// End a session from the backend side: emit `Closed` and stop exporting the
+/// session object.
+pub async fn close_session(
+    connection: &zbus::Connection,
+    session_handle: &zvariant::OwnedObjectPath,
+) -> zbus::Result<()> {
+    let object_server = connection.object_server();
+    let session = object_server
+        .interface::<_, SessionListener>(session_handle)
+        .await?;
+
+    SessionListener::closed(session.signal_emitter()).await?;
+    object_server
+        .remove::<SessionListener, _>(session_handle)
+        .await?;
+
+    Ok(())
+}
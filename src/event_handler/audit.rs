@@ -0,0 +1,469 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use futures::channel::oneshot;
+use serde::Serialize;
+use tracing::{error, info, warn};
+use zbus::zvariant::{OwnedObjectPath, OwnedValue};
+
+use crate::event_handler::events::remote_desktop::RemoteDesktopEvent;
+use crate::event_handler::{AuditOptions, Event, EventHandle, EventResponse, Interface, XdgBypass};
+
+/// Who ended a session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClosedBy {
+    /// The app, through the frontend's `Session.Close`.
+    Client,
+    /// The proxy destination closed its session.
+    Destination,
+    /// The proxy destination left the bus.
+    DestinationExited,
+    /// The policy's `max_duration` ran out.
+    MaxDuration,
+    /// The session's handler failed.
+    Error,
+}
+
+/// One line of the audit log.
+#[derive(Debug, Serialize)]
+struct Record<'a> {
+    /// Seconds since the Unix epoch.
+    time: f64,
+    session: &'a str,
+    app_id: &'a str,
+    #[serde(flatten)]
+    entry: Entry,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Entry {
+    CreateSession {
+        response: u32,
+    },
+    SelectDevices {
+        /// Device types as requested, before the policy limits them.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        types: Option<u32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        persist_mode: Option<u32>,
+        restore_token: bool,
+        response: u32,
+    },
+    Start {
+        response: u32,
+        /// Device types the session got.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        devices: Option<u32>,
+    },
+    ConnectToEis,
+    /// Only written with `keystrokes = true`.
+    Key {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        keycode: Option<i32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        keysym: Option<i32>,
+        state: u32,
+    },
+    Close {
+        closed_by: ClosedBy,
+        /// Seconds the session was open.
+        duration: f64,
+        /// Number of input events per type sent through D-Bus or EIS.
+        input: BTreeMap<&'static str, u64>,
+    },
+}
+
+/// Append-only JSON lines log of the RemoteDesktop sessions.
+///
+/// Shared with the tasks waiting for the handlers' answers, which write the
+/// records of the calls once answered.
+#[derive(Clone, Default)]
+pub struct Audit(Rc<RefCell<AuditLog>>);
+
+#[derive(Default)]
+struct AuditLog {
+    /// Open log file, `None` when disabled.
+    file: Option<(PathBuf, File)>,
+    keystrokes: bool,
+    sessions: HashMap<OwnedObjectPath, SessionAudit>,
+}
+
+struct SessionAudit {
+    app_id: String,
+    created: Instant,
+    input: BTreeMap<&'static str, u64>,
+}
+
+impl Audit {
+    pub fn new(options: &AuditOptions) -> Self {
+        let audit = Self::default();
+        audit.configure(options);
+        audit
+    }
+
+    /// Open the log `options` ask for, or close it when disabled. Sessions
+    /// keep their statistics across a reload.
+    pub fn configure(&self, options: &AuditOptions) {
+        let mut log = self.0.borrow_mut();
+        log.keystrokes = options.keystrokes;
+        let path = options.path.as_deref();
+        if options.enabled && path.is_none() {
+            warn!(
+                "[Audit] No path given and $XDG_STATE_HOME and $HOME are unset, the audit log is disabled"
+            );
+        }
+        if log.file.as_ref().map(|(open, _)| open.as_path()) == path {
+            return;
+        }
+        log.file = path.and_then(|path| match open(path) {
+            Ok(file) => {
+                info!("[Audit] Writing the audit log to {}", path.display());
+                Some((path.to_path_buf(), file))
+            }
+            Err(e) => {
+                error!("[Audit] {:#}", e);
+                None
+            }
+        });
+    }
+
+    /// A session ended, with `closed_by` telling who ended it.
+    pub fn closed(&self, session: &OwnedObjectPath, closed_by: ClosedBy) {
+        let mut log = self.0.borrow_mut();
+        let Some(audit) = log.sessions.remove(session) else {
+            return;
+        };
+        let entry = Entry::Close {
+            closed_by,
+            duration: audit.created.elapsed().as_secs_f64(),
+            input: audit.input,
+        };
+        log.write(session, &audit.app_id, entry);
+    }
+
+    /// Start auditing `session`, `false` if it is audited already.
+    fn begin(&self, session: &OwnedObjectPath, app_id: &str) -> bool {
+        let mut log = self.0.borrow_mut();
+        if log.sessions.contains_key(session) {
+            return false;
+        }
+        log.sessions.insert(
+            session.clone(),
+            SessionAudit {
+                app_id: app_id.to_string(),
+                created: Instant::now(),
+                input: BTreeMap::new(),
+            },
+        );
+        true
+    }
+
+    fn is_audited(&self, session: &OwnedObjectPath) -> bool {
+        self.0.borrow().sessions.contains_key(session)
+    }
+
+    fn created(&self, session: &OwnedObjectPath, response: u32) {
+        self.record(session, Entry::CreateSession { response });
+        // A refused session never gets closed.
+        if response != 0 {
+            self.0.borrow_mut().sessions.remove(session);
+        }
+    }
+
+    fn record(&self, session: &OwnedObjectPath, entry: Entry) {
+        let mut log = self.0.borrow_mut();
+        let Some(audit) = log.sessions.get(session) else {
+            return;
+        };
+        let app_id = audit.app_id.clone();
+        log.write(session, &app_id, entry);
+    }
+
+    /// Count the input event, and record keystrokes when asked to.
+    fn input(&self, session: &OwnedObjectPath, event: &RemoteDesktopEvent) {
+        let Some(kind) = input_kind(event) else {
+            return;
+        };
+        let keystrokes = {
+            let mut log = self.0.borrow_mut();
+            if let Some(audit) = log.sessions.get_mut(session) {
+                *audit.input.entry(kind).or_default() += 1;
+            }
+            log.keystrokes
+        };
+        if !keystrokes {
+            return;
+        }
+        match event {
+            RemoteDesktopEvent::NotifyKeyboardKeycode(key) => self.record(
+                session,
+                Entry::Key {
+                    keycode: Some(key.keycode),
+                    keysym: None,
+                    state: key.state,
+                },
+            ),
+            RemoteDesktopEvent::NotifyKeyboardKeysym(key) => self.record(
+                session,
+                Entry::Key {
+                    keycode: None,
+                    keysym: Some(key.keysym),
+                    state: key.state,
+                },
+            ),
+            _ => {}
+        }
+    }
+}
+
+impl AuditLog {
+    fn write(&mut self, session: &OwnedObjectPath, app_id: &str, entry: Entry) {
+        let Some((path, file)) = &mut self.file else {
+            return;
+        };
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        let record = Record {
+            time,
+            session: session.as_str(),
+            app_id,
+            entry,
+        };
+        let mut line = match serde_json::to_vec(&record) {
+            Ok(line) => line,
+            Err(e) => {
+                error!("[Audit] Failed to serialize a record: {}", e);
+                return;
+            }
+        };
+        line.push(b'\n');
+        // One write per record, so concurrent writers never interleave lines.
+        if let Err(e) = file.write_all(&line) {
+            error!("[Audit] Failed to write to {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Record `event` if it belongs to a RemoteDesktop session. Calls whose answer
+/// matters are recorded once the handler answered them: the returned event's
+/// reply passes the log before reaching the caller.
+pub fn observe(xdg_bypass: &mut XdgBypass, event: EventHandle) -> EventHandle {
+    let audit = xdg_bypass.audit.clone();
+    let session = event.session.clone();
+
+    match &event.event {
+        Event::CreateSession(create_session) => {
+            if create_session.interface != Interface::RemoteDesktop
+                || !audit.begin(&session, &create_session.app_id)
+            {
+                return event;
+            }
+            answered(xdg_bypass, event, move |response| {
+                audit.created(&session, response_code(response))
+            })
+        }
+        Event::Close => {
+            audit.closed(&session, ClosedBy::Client);
+            event
+        }
+        Event::RemoteDesktop(remote_desktop) if audit.is_audited(&session) => {
+            match remote_desktop {
+                RemoteDesktopEvent::SelectDevices(select_devices) => {
+                    let option = |key: &str| {
+                        select_devices
+                            .options
+                            .get(key)
+                            .and_then(|value| u32::try_from(value.clone()).ok())
+                    };
+                    let types = option("types");
+                    let persist_mode = option("persist_mode");
+                    let restore_token = select_devices.options.contains_key("restore_token");
+                    answered(xdg_bypass, event, move |response| {
+                        let entry = Entry::SelectDevices {
+                            types,
+                            persist_mode,
+                            restore_token,
+                            response: response_code(response),
+                        };
+                        audit.record(&session, entry);
+                    })
+                }
+                RemoteDesktopEvent::Start(_) => answered(xdg_bypass, event, move |response| {
+                    let entry = Entry::Start {
+                        response: response_code(response),
+                        devices: started_devices(response),
+                    };
+                    audit.record(&session, entry);
+                }),
+                RemoteDesktopEvent::ConnectToEIS(_) => {
+                    audit.record(&session, Entry::ConnectToEis);
+                    event
+                }
+                input => {
+                    audit.input(&session, input);
+                    event
+                }
+            }
+        }
+        _ => event,
+    }
+}
+
+/// Hand `event` on with a reply that passes `record` before reaching the caller.
+fn answered(
+    xdg_bypass: &mut XdgBypass,
+    event: EventHandle,
+    record: impl FnOnce(&EventResponse) + 'static,
+) -> EventHandle {
+    let EventHandle {
+        session,
+        event,
+        return_tx: caller,
+    } = event;
+    let (return_tx, return_rx) = oneshot::channel();
+    let scheduled = xdg_bypass.scheduler.schedule(async move {
+        // A handler dropping the reply drops the caller's as well.
+        if let Ok(response) = return_rx.await {
+            record(&response);
+            let _ = caller.send(response);
+        }
+    });
+    if let Err(e) = scheduled {
+        error!("[Audit] Failed to schedule the record: {}", e);
+    }
+    EventHandle {
+        session,
+        event,
+        return_tx,
+    }
+}
+
+/// `devices` of a successful `Start`.
+fn started_devices(response: &EventResponse) -> Option<u32> {
+    let EventResponse::Standard(0, results) = response else {
+        return None;
+    };
+    HashMap::<String, OwnedValue>::try_from(results.try_clone().ok()?)
+        .ok()?
+        .get("devices")
+        .and_then(|devices| u32::try_from(devices.clone()).ok())
+}
+
+fn response_code(response: &EventResponse) -> u32 {
+    match response {
+        EventResponse::Standard(response, _) => *response,
+        EventResponse::Value(_) | EventResponse::Fd(_) => 0,
+    }
+}
+
+/// Name of the input event in the statistics, `None` for anything else.
+fn input_kind(event: &RemoteDesktopEvent) -> Option<&'static str> {
+    match event {
+        RemoteDesktopEvent::NotifyPointerMotion(_) => Some("pointer_motion"),
+        RemoteDesktopEvent::NotifyPointerMotionAbsolute(_) => Some("pointer_motion_absolute"),
+        RemoteDesktopEvent::NotifyPointerButton(_) => Some("pointer_button"),
+        RemoteDesktopEvent::NotifyPointerAxis(_) => Some("pointer_axis"),
+        RemoteDesktopEvent::NotifyPointerAxisDiscrete(_) => Some("pointer_axis_discrete"),
+        RemoteDesktopEvent::NotifyKeyboardKeycode(_) => Some("keyboard_keycode"),
+        RemoteDesktopEvent::NotifyKeyboardKeysym(_) => Some("keyboard_keysym"),
+        RemoteDesktopEvent::NotifyTouchDown(_) => Some("touch_down"),
+        RemoteDesktopEvent::NotifyTouchMotion(_) => Some("touch_motion"),
+        RemoteDesktopEvent::NotifyTouchUp(_) => Some("touch_up"),
+        RemoteDesktopEvent::SelectDevices(_)
+        | RemoteDesktopEvent::Start(_)
+        | RemoteDesktopEvent::ConnectToEIS(_)
+        | RemoteDesktopEvent::GetPropertiesAvilableDeviceTypes
+        | RemoteDesktopEvent::GetPropertiesVersion => None,
+    }
+}
+
+/// Open `path` for appending, readable by the user only.
+fn open(path: &Path) -> anyhow::Result<File> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| {
+            anyhow::anyhow!("Failed to create the directory {}: {}", dir.display(), e)
+        })?;
+    }
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(0o600)
+        .open(path)
+        .map_err(|e| anyhow::anyhow!("Failed to open the audit log {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_handler::events::remote_desktop::NotifyKeyboardKeycode;
+    use crate::event_handler::testing::{handle, request, session, xdg_bypass};
+    use crate::event_handler::{CreateSession, XdgBypassConfig};
+
+    #[test]
+    fn key_events_are_audited() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let mut config = XdgBypassConfig::default();
+        config.audit.path = Some(path.clone());
+        let mut event_loop = calloop::EventLoop::try_new().unwrap();
+        let mut xdg_bypass = xdg_bypass(&event_loop, config);
+
+        let session = session("t");
+        let create_session = Event::CreateSession(CreateSession {
+            interface: Interface::RemoteDesktop,
+            handle: handle("t"),
+            app_id: "org.example.App".to_string(),
+            options: HashMap::new(),
+        });
+        request(&mut event_loop, &mut xdg_bypass, &session, create_session);
+        let keycode = RemoteDesktopEvent::NotifyKeyboardKeycode(NotifyKeyboardKeycode {
+            options: HashMap::new(),
+            keycode: 30,
+            state: 1,
+        });
+        request(
+            &mut event_loop,
+            &mut xdg_bypass,
+            &session,
+            Event::RemoteDesktop(keycode),
+        );
+        request(&mut event_loop, &mut xdg_bypass, &session, Event::Close);
+
+        let log = fs::read_to_string(&path).unwrap();
+        let close = log.lines().last().unwrap();
+        assert!(close.contains(r#""event":"close""#), "{}", log);
+        assert!(
+            close.contains(r#""input":{"keyboard_keycode":1}"#),
+            "{}",
+            log
+        );
+    }
+
+    #[test]
+    fn records_are_single_json_lines() {
+        let record = Record {
+            time: 1.5,
+            session: "/org/freedesktop/portal/desktop/session/1_1/s",
+            app_id: "org.example.App",
+            entry: Entry::Close {
+                closed_by: ClosedBy::MaxDuration,
+                duration: 2.0,
+                input: BTreeMap::from([("keyboard_keycode", 3)]),
+            },
+        };
+        assert_eq!(
+            serde_json::to_string(&record).unwrap(),
+            r#"{"time":1.5,"session":"/org/freedesktop/portal/desktop/session/1_1/s","app_id":"org.example.App","event":"close","closed_by":"max_duration","duration":2.0,"input":{"keyboard_keycode":3}}"#
+        );
+    }
+}
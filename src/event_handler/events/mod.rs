@@ -1,2 +1,157 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+
 pub mod remote_desktop;
 pub mod screen_cast;
+
+/// Whether input payloads are logged, see [`Payload`].
+static LOG_INPUT: AtomicBool = AtomicBool::new(false);
+
+/// Log the keys, positions and motion of input events from now on, or stop.
+pub fn set_log_input(log_input: bool) {
+    LOG_INPUT.store(log_input, Ordering::Relaxed);
+}
+
+/// Input payload of an event, formatted as `<redacted>` unless enabled with
+/// [`set_log_input`]. Keeps what is typed in remote sessions out of the logs.
+pub struct Payload<'a, T>(pub &'a T);
+
+impl<T: fmt::Debug> fmt::Debug for Payload<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        redact(f, LOG_INPUT.load(Ordering::Relaxed), |f| self.0.fmt(f))
+    }
+}
+
+impl<T: fmt::LowerHex> fmt::LowerHex for Payload<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        redact(f, LOG_INPUT.load(Ordering::Relaxed), |f| self.0.fmt(f))
+    }
+}
+
+/// Format a payload with `value` when `log_input` is set, as `<redacted>`
+/// otherwise.
+fn redact(
+    f: &mut fmt::Formatter<'_>,
+    log_input: bool,
+    value: impl FnOnce(&mut fmt::Formatter<'_>) -> fmt::Result,
+) -> fmt::Result {
+    match log_input {
+        true => value(f),
+        false => f.write_str("<redacted>"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    use super::remote_desktop::{
+        NotifyKeyboardKeycode, NotifyKeyboardKeysym, NotifyPointerAxisDiscrete,
+        NotifyPointerMotion, NotifyPointerMotionAbsolute, NotifyTouchDown,
+    };
+    use super::*;
+
+    /// Held by tests formatting payloads, `LOG_INPUT` is shared by all of them.
+    static LOG_INPUT_LOCK: Mutex<()> = Mutex::new(());
+
+    /// Formatted events paired with how they read once input is logged.
+    fn events() -> Vec<(String, &'static str)> {
+        vec![
+            (
+                format!(
+                    "{:?}",
+                    NotifyKeyboardKeycode {
+                        options: HashMap::new(),
+                        keycode: 30,
+                        state: 1,
+                    }
+                ),
+                "NotifyKeyboardKeycode { options: {}, keycode: 30, state: 1 }",
+            ),
+            (
+                format!(
+                    "{:?}",
+                    NotifyKeyboardKeysym {
+                        options: HashMap::new(),
+                        keysym: 0x61,
+                        state: 0,
+                    }
+                ),
+                "NotifyKeyboardKeysym { options: {}, keysym: 97, state: 0 }",
+            ),
+            (format!("{:#x}", Payload(&0x61)), "0x61"),
+            (
+                format!(
+                    "{:?}",
+                    NotifyPointerMotion {
+                        options: HashMap::new(),
+                        dx: 1.5,
+                        dy: -2.0,
+                    }
+                ),
+                "NotifyPointerMotion { options: {}, dx: 1.5, dy: -2.0 }",
+            ),
+            (
+                format!(
+                    "{:?}",
+                    NotifyPointerMotionAbsolute {
+                        options: HashMap::new(),
+                        stream: 44,
+                        x: 10.0,
+                        y: 20.0,
+                    }
+                ),
+                "NotifyPointerMotionAbsolute { options: {}, stream: 44, x: 10.0, y: 20.0 }",
+            ),
+            (
+                format!(
+                    "{:?}",
+                    NotifyPointerAxisDiscrete {
+                        options: HashMap::new(),
+                        axis: 0,
+                        steps: -1,
+                    }
+                ),
+                "NotifyPointerAxisDiscrete { options: {}, axis: 0, steps: -1 }",
+            ),
+            (
+                format!(
+                    "{:?}",
+                    NotifyTouchDown {
+                        options: HashMap::new(),
+                        stream: 44,
+                        slot: 0,
+                        x: 10.0,
+                        y: 20.0,
+                    }
+                ),
+                "NotifyTouchDown { options: {}, stream: 44, slot: 0, x: 10.0, y: 20.0 }",
+            ),
+        ]
+    }
+
+    #[test]
+    fn input_is_redacted_unless_enabled() {
+        let _lock = LOG_INPUT_LOCK.lock().unwrap();
+
+        let redacted = [
+            "NotifyKeyboardKeycode { options: {}, keycode: <redacted>, state: 1 }",
+            "NotifyKeyboardKeysym { options: {}, keysym: <redacted>, state: 0 }",
+            "<redacted>",
+            "NotifyPointerMotion { options: {}, dx: <redacted>, dy: <redacted> }",
+            "NotifyPointerMotionAbsolute { options: {}, stream: 44, x: <redacted>, y: <redacted> }",
+            "NotifyPointerAxisDiscrete { options: {}, axis: 0, steps: <redacted> }",
+            "NotifyTouchDown { options: {}, stream: 44, slot: 0, x: <redacted>, y: <redacted> }",
+        ];
+        let formatted: Vec<String> = events().into_iter().map(|(event, _)| event).collect();
+        assert_eq!(formatted, redacted);
+
+        set_log_input(true);
+        let events = events();
+        set_log_input(false);
+        for (event, logged) in events {
+            assert_eq!(event, logged);
+        }
+    }
+}
@@ -1,7 +1,10 @@
 use std::collections::HashMap;
+use std::fmt;
 
 use zbus::zvariant;
 
+use crate::event_handler::events::Payload;
+
 #[derive(Debug)]
 pub enum RemoteDesktopEvent {
     SelectDevices(SelectDevices),
@@ -13,6 +16,10 @@ pub enum RemoteDesktopEvent {
     NotifyPointerAxisDiscrete(NotifyPointerAxisDiscrete),
     NotifyKeyboardKeycode(NotifyKeyboardKeycode),
     NotifyKeyboardKeysym(NotifyKeyboardKeysym),
+    NotifyTouchDown(NotifyTouchDown),
+    NotifyTouchMotion(NotifyTouchMotion),
+    NotifyTouchUp(NotifyTouchUp),
+    ConnectToEIS(ConnectToEIS),
     GetPropertiesAvilableDeviceTypes,
     GetPropertiesVersion,
 }
@@ -20,7 +27,6 @@ pub enum RemoteDesktopEvent {
 #[derive(Debug)]
 pub struct SelectDevices {
     pub handle: zvariant::ObjectPath<'static>,
-    pub session_handle: zvariant::ObjectPath<'static>,
     pub app_id: String,
     pub options: HashMap<String, zvariant::OwnedValue>,
 }
@@ -28,65 +34,173 @@ pub struct SelectDevices {
 #[derive(Debug)]
 pub struct Start {
     pub handle: zvariant::ObjectPath<'static>,
-    pub session_handle: zvariant::ObjectPath<'static>,
     pub app_id: String,
     pub parent_window: String,
     pub options: HashMap<String, zvariant::OwnedValue>,
 }
 
-#[derive(Debug)]
 pub struct NotifyPointerMotion {
-    pub session_handle: zvariant::ObjectPath<'static>,
     pub options: HashMap<String, zvariant::OwnedValue>,
     pub dx: f64,
     pub dy: f64,
 }
 
-#[derive(Debug)]
+impl fmt::Debug for NotifyPointerMotion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NotifyPointerMotion")
+            .field("options", &self.options)
+            .field("dx", &Payload(&self.dx))
+            .field("dy", &Payload(&self.dy))
+            .finish()
+    }
+}
+
 pub struct NotifyPointerMotionAbsolute {
-    pub session_handle: zvariant::ObjectPath<'static>,
     pub options: HashMap<String, zvariant::OwnedValue>,
     pub stream: u32,
     pub x: f64,
     pub y: f64,
 }
 
-#[derive(Debug)]
+impl fmt::Debug for NotifyPointerMotionAbsolute {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NotifyPointerMotionAbsolute")
+            .field("options", &self.options)
+            .field("stream", &self.stream)
+            .field("x", &Payload(&self.x))
+            .field("y", &Payload(&self.y))
+            .finish()
+    }
+}
+
 pub struct NotifyPointerButton {
-    pub session_handle: zvariant::ObjectPath<'static>,
     pub options: HashMap<String, zvariant::OwnedValue>,
     pub button: i32,
     pub state: u32,
 }
 
-#[derive(Debug)]
+impl fmt::Debug for NotifyPointerButton {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NotifyPointerButton")
+            .field("options", &self.options)
+            .field("button", &self.button)
+            .field("state", &self.state)
+            .finish()
+    }
+}
+
 pub struct NotifyPointerAxis {
-    pub session_handle: zvariant::ObjectPath<'static>,
     pub options: HashMap<String, zvariant::OwnedValue>,
     pub dx: f64,
     pub dy: f64,
 }
 
-#[derive(Debug)]
+impl fmt::Debug for NotifyPointerAxis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NotifyPointerAxis")
+            .field("options", &self.options)
+            .field("dx", &Payload(&self.dx))
+            .field("dy", &Payload(&self.dy))
+            .finish()
+    }
+}
+
 pub struct NotifyPointerAxisDiscrete {
-    pub session_handle: zvariant::ObjectPath<'static>,
     pub options: HashMap<String, zvariant::OwnedValue>,
     pub axis: u32,
     pub steps: i32,
 }
 
-#[derive(Debug)]
+impl fmt::Debug for NotifyPointerAxisDiscrete {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NotifyPointerAxisDiscrete")
+            .field("options", &self.options)
+            .field("axis", &self.axis)
+            .field("steps", &Payload(&self.steps))
+            .finish()
+    }
+}
+
 pub struct NotifyKeyboardKeycode {
-    pub session_handle: zvariant::ObjectPath<'static>,
     pub options: HashMap<String, zvariant::OwnedValue>,
     pub keycode: i32,
     pub state: u32,
 }
 
-#[derive(Debug)]
+impl fmt::Debug for NotifyKeyboardKeycode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NotifyKeyboardKeycode")
+            .field("options", &self.options)
+            .field("keycode", &Payload(&self.keycode))
+            .field("state", &self.state)
+            .finish()
+    }
+}
+
 pub struct NotifyKeyboardKeysym {
-    pub session_handle: zvariant::ObjectPath<'static>,
     pub options: HashMap<String, zvariant::OwnedValue>,
     pub keysym: i32,
     pub state: u32,
 }
+
+impl fmt::Debug for NotifyKeyboardKeysym {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NotifyKeyboardKeysym")
+            .field("options", &self.options)
+            .field("keysym", &Payload(&self.keysym))
+            .field("state", &self.state)
+            .finish()
+    }
+}
+
+pub struct NotifyTouchDown {
+    pub options: HashMap<String, zvariant::OwnedValue>,
+    pub stream: u32,
+    pub slot: u32,
+    pub x: f64,
+    pub y: f64,
+}
+
+impl fmt::Debug for NotifyTouchDown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NotifyTouchDown")
+            .field("options", &self.options)
+            .field("stream", &self.stream)
+            .field("slot", &self.slot)
+            .field("x", &Payload(&self.x))
+            .field("y", &Payload(&self.y))
+            .finish()
+    }
+}
+
+pub struct NotifyTouchMotion {
+    pub options: HashMap<String, zvariant::OwnedValue>,
+    pub stream: u32,
+    pub slot: u32,
+    pub x: f64,
+    pub y: f64,
+}
+
+impl fmt::Debug for NotifyTouchMotion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NotifyTouchMotion")
+            .field("options", &self.options)
+            .field("stream", &self.stream)
+            .field("slot", &self.slot)
+            .field("x", &Payload(&self.x))
+            .field("y", &Payload(&self.y))
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+pub struct NotifyTouchUp {
+    pub options: HashMap<String, zvariant::OwnedValue>,
+    pub slot: u32,
+}
+
+#[derive(Debug)]
+pub struct ConnectToEIS {
+    pub app_id: String,
+    pub options: HashMap<String, zvariant::OwnedValue>,
+}
@@ -15,7 +15,6 @@ pub enum ScreenCastEvent {
 #[derive(Debug)]
 pub struct SelectSources {
     pub handle: zvariant::ObjectPath<'static>,
-    pub session_handle: zvariant::ObjectPath<'static>,
     pub app_id: String,
     pub options: HashMap<String, zvariant::OwnedValue>,
 }
@@ -23,7 +22,6 @@ pub struct SelectSources {
 #[derive(Debug)]
 pub struct Start {
     pub handle: zvariant::ObjectPath<'static>,
-    pub session_handle: zvariant::ObjectPath<'static>,
     pub app_id: String,
     pub parent_window: String,
     pub options: HashMap<String, zvariant::OwnedValue>,
@@ -31,7 +29,5 @@ pub struct Start {
 
 #[derive(Debug)]
 pub struct OpenPipeWireRemote {
-    pub session_handle: zvariant::ObjectPath<'static>,
-    pub app_id: String,
     pub options: HashMap<String, zvariant::OwnedValue>,
 }
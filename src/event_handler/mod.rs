@@ -1,16 +1,31 @@
-use calloop::LoopSignal;
+use calloop::{LoopHandle, LoopSignal, channel};
 use futures::channel::oneshot;
 use std::collections::HashMap;
-use tracing::{debug, error};
+use std::path::PathBuf;
+use std::time::Duration;
+use tracing::{debug, error, info};
 use zbus::{
     Connection,
     zvariant::{self, OwnedObjectPath},
 };
 
-use crate::event_handler::events::remote_desktop::RemoteDesktopEvent;
+use crate::event_handler::audit::{Audit, ClosedBy};
+use crate::event_handler::events::remote_desktop::{
+    RemoteDesktopEvent, Start as RemoteDesktopStart,
+};
 use crate::event_handler::events::screen_cast::ScreenCastEvent;
+use crate::event_handler::policy::{PolicyRule, SessionPolicies};
+use crate::event_handler::proxy::DownstreamPaths;
+use crate::event_handler::proxy::destinations::{Destinations, select_mode, watch_destinations};
+use crate::event_handler::proxy::remote_desktop::RemoteDesktopProxy;
+use crate::event_handler::proxy::screen_cast::ScreenCastProxy;
+use crate::event_handler::server::consent::Answer;
+use crate::event_handler::server::grants::Restore;
+use crate::event_handler::server::remote_desktop::RemoteDesktopServer;
 
+pub mod audit;
 pub mod events;
+pub mod policy;
 pub mod proxy;
 pub mod server;
 
@@ -18,36 +33,343 @@ pub struct XdgBypass {
     pub config: XdgBypassConfig,
 
     pub stop_signal: LoopSignal,
+    /// Handle of the event loop, to serve sockets such as EIS connections on it.
+    pub loop_handle: LoopHandle<'static, XdgBypass>,
     pub scheduler: calloop::futures::Scheduler<()>,
     pub connection: zbus::Connection,
+    /// Connection the portal interfaces are served on, used to emit session signals.
+    pub listener_connection: Option<zbus::Connection>,
 
     pub sessions: HashMap<OwnedObjectPath, Box<dyn EventHandler>>,
+    /// Proxy destinations whose owner is watched.
+    pub destinations: Destinations,
+    /// Device types approved with `persist_mode` 1 per app id, kept until the
+    /// daemon exits.
+    pub transient_grants: HashMap<String, u32>,
+    /// Restore tokens issued with `persist_mode` 1, until used or the daemon exits.
+    pub restore_tokens: HashMap<String, Restore>,
+    /// Policy rules of the running RemoteDesktop sessions.
+    pub policies: SessionPolicies,
+    pub audit: Audit,
 }
 
 impl XdgBypass {
     pub fn new(
         config: XdgBypassConfig,
         stop_signal: LoopSignal,
+        loop_handle: LoopHandle<'static, XdgBypass>,
         scheduler: calloop::futures::Scheduler<()>,
         connection: Connection,
+        listener_connection: Option<Connection>,
     ) -> Self {
-        Self {
+        let audit = Audit::new(&config.audit);
+        let mut xdg_bypass = Self {
             config,
             stop_signal,
+            loop_handle,
             scheduler,
             connection,
+            listener_connection,
             sessions: HashMap::new(),
-        }
+            destinations: Destinations::default(),
+            transient_grants: HashMap::new(),
+            restore_tokens: HashMap::new(),
+            policies: SessionPolicies::default(),
+            audit,
+        };
+        watch_destinations(&mut xdg_bypass);
+        xdg_bypass
     }
 
     pub fn handle(&mut self, event: EventHandle) {
         debug!("Event: {:#?}", event);
+        let event = audit::observe(self, event);
+
+        match &event.event {
+            Event::CreateSession(create_session) => {
+                if self.sessions.contains_key(&event.session) {
+                    error!("[XdgBypass] Session {} already exists", event.session);
+                    return_response(
+                        event.return_tx,
+                        EventResponse::Standard(2, empty_results()),
+                        "XdgBypass.CreateSession",
+                    );
+                    return;
+                }
+
+                let interface = create_session.interface;
+                let Some(event) = policy::apply(self, event) else {
+                    return;
+                };
+                select_mode(self, interface, event, Self::create_session);
+                return;
+            }
+            Event::Close => {
+                policy::forget(self, &event.session);
+                let Some(mut handler) = self.sessions.remove(&event.session) else {
+                    error!("[XdgBypass] Unknown session {}", event.session);
+                    return_response(
+                        event.return_tx,
+                        EventResponse::Standard(2, empty_results()),
+                        "XdgBypass.Close",
+                    );
+                    return;
+                };
+
+                let session = event.session.clone();
+                if let Err(e) = handler.handle(self, event) {
+                    error!("[XdgBypass] Session {} failed to close: {:#}", session, e);
+                }
+                debug!("[XdgBypass] Session {} closed", session);
+                return;
+            }
+            Event::RemoteDesktop(
+                RemoteDesktopEvent::GetPropertiesAvilableDeviceTypes
+                | RemoteDesktopEvent::GetPropertiesVersion,
+            ) => {
+                select_mode(self, Interface::RemoteDesktop, event, Self::handle_property);
+                return;
+            }
+            Event::ScreenCast(
+                ScreenCastEvent::GetPropertiesAvailableSourceTypes
+                | ScreenCastEvent::GetPropertiesAvailableCursorModes
+                | ScreenCastEvent::GetPropertiesVersion,
+            ) => {
+                select_mode(self, Interface::ScreenCast, event, Self::handle_property);
+                return;
+            }
+            _ => {}
+        }
+
+        if let Some(event) = policy::apply(self, event) {
+            self.dispatch(event);
+        }
+    }
+
+    /// Swap the configuration. Only sessions created from now on use it,
+    /// running sessions keep their handlers and devices as they are.
+    pub fn reload_config(&mut self, config: XdgBypassConfig) {
+        debug!("[XdgBypass] New config: {:?}", config);
+        self.config = config;
+        self.audit.configure(&self.config.audit);
+        watch_destinations(self);
+        info!(
+            "[XdgBypass] Config reloaded, {} running sessions keep their previous settings",
+            self.sessions.len()
+        );
+    }
+
+    /// Hand the event over to the handler owning its session.
+    pub fn dispatch(&mut self, event: EventHandle) {
+        let session = event.session.clone();
+
+        // The handler needs `&mut self`, so take it out of the map while it runs.
+        let Some(mut handler) = self.sessions.remove(&session) else {
+            error!("[XdgBypass] Unknown session {}", session);
+            return_response(
+                event.return_tx,
+                EventResponse::Standard(2, empty_results()),
+                "XdgBypass",
+            );
+            return;
+        };
+
+        if let Err(e) = handler.handle(self, event) {
+            error!(
+                "[XdgBypass] Session {} failed to handle event, closing it: {:#}",
+                session, e
+            );
+            self.sessions.insert(session.clone(), handler);
+            self.close_session(&session, ClosedBy::Error);
+            return;
+        }
+
+        self.sessions.insert(session, handler);
+    }
+
+    /// Run `future` on the executor, then hand its output to `then` back on
+    /// the event loop. `then` never runs when the future is dropped unfinished.
+    pub fn schedule_then<T: 'static>(
+        &self,
+        future: impl Future<Output = T> + 'static,
+        then: impl FnOnce(&mut XdgBypass, T) + 'static,
+    ) -> anyhow::Result<()> {
+        let (sender, receiver) = channel::channel::<T>();
+        let mut then = Some(then);
+        self.loop_handle
+            .insert_source(receiver, move |event, _, xdg_bypass| {
+                if let (channel::Event::Msg(output), Some(then)) = (event, then.take()) {
+                    then(xdg_bypass, output);
+                }
+            })
+            .map_err(|e| anyhow::anyhow!("Failed to insert the channel source: {}", e))?;
+        self.scheduler
+            .schedule(async move {
+                // Dropping the sender afterwards removes the source again.
+                let _ = sender.send(future.await);
+            })
+            .map_err(|e| anyhow::anyhow!("Failed to schedule the future: {}", e))
+    }
+
+    /// End a session from the backend side. The handler closes it as if the
+    /// client did, which also ends a proxied session at its destination, and
+    /// the frontend is told with `Session.Closed`.
+    pub fn close_session(&mut self, session: &OwnedObjectPath, closed_by: ClosedBy) {
+        match (self.sessions.remove(session), closed_by) {
+            // The destination ended the session itself, only our side is left.
+            (Some(_), ClosedBy::Destination | ClosedBy::DestinationExited) => {}
+            (Some(mut handler), _) => {
+                let (return_tx, _) = oneshot::channel();
+                let close = EventHandle {
+                    session: session.clone(),
+                    event: Event::Close,
+                    return_tx,
+                };
+                if let Err(e) = handler.handle(self, close) {
+                    error!("[XdgBypass] Session {} failed to close: {:#}", session, e);
+                }
+            }
+            (None, _) => {}
+        }
+        policy::forget(self, session);
+        self.audit.closed(session, closed_by);
+
+        let Some(listener_connection) = self.listener_connection.clone() else {
+            return;
+        };
+        let session = session.clone();
+        let _ = self
+            .scheduler
+            .schedule(async move {
+                if let Err(e) =
+                    crate::dbus_listener::close_session(&listener_connection, &session).await
+                {
+                    error!(
+                        "[XdgBypass] Failed to emit Closed for session {}: {}",
+                        session, e
+                    );
+                }
+            })
+            .map_err(|e| error!("[XdgBypass] Failed to schedule session close: {}", e));
+    }
+
+    /// Create the session's handler for the working mode picked for it, then
+    /// let the handler answer `CreateSession`.
+    fn create_session(&mut self, interface: Interface, mode: WorkingMode, event: EventHandle) {
+        match self.new_handler(interface, &mode, event.session.clone()) {
+            Ok(handler) => {
+                self.sessions.insert(event.session.clone(), handler);
+            }
+            Err(e) => {
+                error!(
+                    "[XdgBypass] Failed to create handler for session {}: {:#}",
+                    event.session, e
+                );
+                policy::forget(self, &event.session);
+                return_response(
+                    event.return_tx,
+                    EventResponse::Standard(2, empty_results()),
+                    "XdgBypass.CreateSession",
+                );
+                return;
+            }
+        }
+
+        let event = self.forget_unless_created(event);
+        self.dispatch(event);
+    }
+
+    /// Hand on `CreateSession` with a reply that drops the session and its
+    /// policy again when the handler refuses it, fails or never answers.
+    fn forget_unless_created(&mut self, event: EventHandle) -> EventHandle {
+        let EventHandle {
+            session,
+            event,
+            return_tx: caller,
+        } = event;
+
+        let (return_tx, return_rx) = oneshot::channel();
+        let created = session.clone();
+        let scheduled = self.schedule_then(return_rx, move |xdg_bypass, response| {
+            if !matches!(response, Ok(EventResponse::Standard(0, _))) {
+                debug!("[XdgBypass] Session {} was not created", created);
+                // Dropping the handler stops whatever it watches.
+                xdg_bypass.sessions.remove(&created);
+                policy::forget(xdg_bypass, &created);
+            }
+            if let Ok(response) = response {
+                return_response(caller, response, "XdgBypass.CreateSession");
+            }
+        });
+        if let Err(e) = scheduled {
+            error!(
+                "[XdgBypass] Failed to wait for session {} to be created: {:#}",
+                session, e
+            );
+        }
+
+        EventHandle {
+            session,
+            event,
+            return_tx,
+        }
+    }
+
+    /// Properties are not bound to a session, answer them with a throwaway
+    /// handler of the working mode a new session would get.
+    fn handle_property(&mut self, interface: Interface, mode: WorkingMode, event: EventHandle) {
+        match self.new_handler(interface, &mode, event.session.clone()) {
+            Ok(mut handler) => {
+                if let Err(e) = handler.handle(self, event) {
+                    error!("[XdgBypass] Failed to get property: {:#}", e);
+                }
+            }
+            Err(e) => {
+                error!("[XdgBypass] Failed to create handler for property: {:#}", e);
+            }
+        }
+    }
+
+    fn new_handler(
+        &mut self,
+        interface: Interface,
+        mode: &WorkingMode,
+        session: OwnedObjectPath,
+    ) -> anyhow::Result<Box<dyn EventHandler>> {
+        match (interface, mode) {
+            (Interface::RemoteDesktop, WorkingMode::Server) => {
+                RemoteDesktopServer::new(self, session)
+            }
+            (Interface::RemoteDesktop, WorkingMode::Proxy(destination)) => Ok(Box::new(
+                RemoteDesktopProxy::with_destination(self, session, destination)?,
+            )),
+            (Interface::ScreenCast, WorkingMode::Proxy(destination)) => {
+                Ok(Box::new(ScreenCastProxy::with_destination(
+                    self,
+                    session,
+                    destination,
+                    DownstreamPaths::new(),
+                    false,
+                )?))
+            }
+            (Interface::ScreenCast, WorkingMode::Server) => Err(anyhow::anyhow!(
+                "[XdgBypass] ScreenCast can only be proxied, configure a proxy destination"
+            )),
+        }
     }
 }
 
 pub trait EventHandler {
     fn handle(&mut self, xdg_bypass: &mut XdgBypass, event: EventHandle) -> anyhow::Result<()>;
 
+    /// Whether the session forwards calls to the backend owning `service_name`,
+    /// and so ends when that backend leaves the bus.
+    fn uses_destination(&self, _service_name: &str) -> bool {
+        false
+    }
+
+    /// Create the handler for `session`, boxed so it can be stored in [`XdgBypass::sessions`].
+    #[allow(clippy::new_ret_no_self)]
     fn new(
         xdg_bypass: &mut XdgBypass,
         session: OwnedObjectPath,
@@ -56,25 +378,178 @@ pub trait EventHandler {
         Self: Sized;
 }
 
+#[derive(Debug, Clone, Default)]
 pub struct XdgBypassConfig {
-    pub remote_desktop_mode: WorkingMode,
+    /// Working modes of every portal interface in order of preference, the
+    /// first available one is used. Interfaces missing here are served locally.
+    pub interfaces: HashMap<Interface, Vec<WorkingMode>>,
+    pub devices: DeviceOptions,
+    pub proxy: ProxyOptions,
+    pub consent: ConsentOptions,
+    /// Policy rules of RemoteDesktop sessions, the first matching one applies.
+    pub policy: Vec<PolicyRule>,
+    pub audit: AuditOptions,
 }
 
+impl XdgBypassConfig {
+    /// Preferred working mode of `interface`.
+    pub fn mode(&self, interface: Interface) -> &WorkingMode {
+        &self.modes(interface)[0]
+    }
+
+    /// Working modes of `interface`, never empty.
+    pub fn modes(&self, interface: Interface) -> &[WorkingMode] {
+        static SERVER: [WorkingMode; 1] = [WorkingMode::Server];
+        match self.interfaces.get(&interface) {
+            Some(modes) if !modes.is_empty() => modes,
+            _ => &SERVER,
+        }
+    }
+
+    /// Proxy destinations of `interface`, in order of preference.
+    pub fn proxy_destinations(
+        &self,
+        interface: Interface,
+    ) -> impl Iterator<Item = &ProxyDestination> {
+        self.modes(interface).iter().filter_map(|mode| match mode {
+            WorkingMode::Proxy(destination) => Some(destination),
+            WorkingMode::Server => None,
+        })
+    }
+
+    /// Policy rule of `app_id`, prompting for every device type when no rule matches.
+    pub fn policy_rule(&self, app_id: &str) -> PolicyRule {
+        self.policy
+            .iter()
+            .find(|rule| rule.matches(app_id))
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+/// Portal interfaces the backend implements.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Interface {
+    RemoteDesktop,
+    ScreenCast,
+}
+
+impl Interface {
+    pub const ALL: [Interface; 2] = [Interface::RemoteDesktop, Interface::ScreenCast];
+
+    /// Name of the interface's section in the config file.
+    pub fn key(self) -> &'static str {
+        match self {
+            Interface::RemoteDesktop => "remote_desktop",
+            Interface::ScreenCast => "screen_cast",
+        }
+    }
+
+    /// D-Bus interface name.
+    pub fn name(self) -> &'static str {
+        match self {
+            Interface::RemoteDesktop => "org.freedesktop.impl.portal.RemoteDesktop",
+            Interface::ScreenCast => "org.freedesktop.impl.portal.ScreenCast",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum WorkingMode {
     Server,
     Proxy(ProxyDestination),
 }
 
+#[derive(Debug, Clone)]
 pub struct ProxyDestination {
     service_name: String,
     object_path: zvariant::OwnedObjectPath,
 }
 
+impl ProxyDestination {
+    pub fn new(service_name: String, object_path: zvariant::OwnedObjectPath) -> Self {
+        Self {
+            service_name,
+            object_path,
+        }
+    }
+
+    pub fn service_name(&self) -> &str {
+        &self.service_name
+    }
+
+    pub fn object_path(&self) -> &zvariant::OwnedObjectPath {
+        &self.object_path
+    }
+}
+
+/// Options of the devices created in server mode.
+#[derive(Debug, Clone, Default)]
+pub struct DeviceOptions {
+    /// XKB layout and variant used to resolve `NotifyKeyboardKeysym`, the
+    /// system default when unset.
+    pub keyboard_layout: Option<String>,
+    pub keyboard_variant: Option<String>,
+}
+
+/// Options of the calls forwarded in proxy mode.
+#[derive(Debug, Clone, Default)]
+pub struct ProxyOptions {
+    /// Limit for every call to the destination but `Start`, `None` waits forever.
+    pub timeout: Option<Duration>,
+    /// Limit for `Start`, which usually waits for the user to answer a dialog.
+    pub start_timeout: Option<Duration>,
+}
+
+/// How the user approves `Start` of a session served locally.
+#[derive(Debug, Clone)]
+pub struct ConsentOptions {
+    pub prompt: ConsentMethod,
+    /// Used when `prompt` can't be shown, e.g. without a notification server.
+    pub fallback: ConsentMethod,
+    /// Time the user has to answer before the request is denied, `None` waits forever.
+    pub timeout: Option<Duration>,
+}
+
+impl Default for ConsentOptions {
+    fn default() -> Self {
+        Self {
+            prompt: ConsentMethod::Notification,
+            fallback: ConsentMethod::Terminal,
+            timeout: Some(Duration::from_secs(60)),
+        }
+    }
+}
+
+/// Audit log of the RemoteDesktop sessions.
+#[derive(Debug, Clone, Default)]
+pub struct AuditOptions {
+    /// Whether the log was asked for, it stays disabled without a path.
+    pub enabled: bool,
+    /// File the records are appended to, `None` disables the log.
+    pub path: Option<PathBuf>,
+    /// Record every key pressed and released, not only how many.
+    pub keystrokes: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConsentMethod {
+    /// Desktop notification with Allow and Deny actions.
+    Notification,
+    /// Question on the daemon's terminal, only when stdin is one.
+    Terminal,
+    /// Approve without asking.
+    Approve,
+    /// Deny without asking.
+    Deny,
+}
+
 #[derive(Debug)]
 pub struct EventHandle {
-    session: OwnedObjectPath,
-    event: Event,
-    return_tx: oneshot::Sender<EventResponse>,
+    pub session: OwnedObjectPath,
+    pub event: Event,
+    pub return_tx: oneshot::Sender<EventResponse>,
 }
 
 pub fn return_response(
@@ -87,23 +562,280 @@ pub fn return_response(
         .unwrap_or_else(|_| error!("[{}] Failed when return error", module))
 }
 
+/// Empty `a{sv}` results, for responses that carry no data.
+pub fn empty_results() -> zvariant::OwnedValue {
+    zvariant::OwnedValue::from(HashMap::<String, zvariant::OwnedValue>::new())
+}
+
+#[derive(Debug)]
 pub enum EventResponse {
     Standard(u32, zvariant::OwnedValue),
     Value(zvariant::OwnedValue),
+    /// A file descriptor handed back to the caller, e.g. from `ConnectToEIS`.
+    Fd(zvariant::OwnedFd),
 }
 
 #[derive(Debug)]
 pub enum Event {
     CreateSession(CreateSession),
     Close,
+    CloseRequest(CloseRequest),
+    StreamsStarted(StreamsStarted),
+    ConsentAnswered(ConsentAnswered),
+    SelectionRestored(SelectionRestored),
     RemoteDesktop(RemoteDesktopEvent),
     ScreenCast(ScreenCastEvent),
 }
 
 #[derive(Debug)]
 pub struct CreateSession {
+    /// Interface whose `CreateSession` was called, selects the session's handler.
+    pub interface: Interface,
     pub handle: zvariant::ObjectPath<'static>,
-    pub session_handle: zvariant::ObjectPath<'static>,
     pub app_id: String,
     pub options: HashMap<String, zvariant::OwnedValue>,
 }
+
+/// The frontend closed the `Request` object of a pending call, the handler
+/// should abort it and answer the call with response code 1.
+#[derive(Debug)]
+pub struct CloseRequest {
+    pub handle: zvariant::ObjectPath<'static>,
+}
+
+/// Answer of the proxied ScreenCast `Start` of a RemoteDesktop session, fed
+/// back into the session so its own `Start` can finish with the streams.
+#[derive(Debug)]
+pub struct StreamsStarted {
+    pub response: u32,
+    pub results: HashMap<String, zvariant::OwnedValue>,
+}
+
+/// The user's answer to the consent prompt of a pending `Start`, fed back into
+/// the session to go on with it.
+#[derive(Debug)]
+pub struct ConsentAnswered {
+    pub start: RemoteDesktopStart,
+    pub answer: Answer,
+}
+
+/// Device types of the restore token passed to `SelectDevices`, looked up in
+/// the permission store and fed back into the session to answer the call.
+/// `None` when the token is unknown, used or issued to another app.
+#[derive(Debug)]
+pub struct SelectionRestored {
+    pub device_types: Option<u32>,
+}
+
+/// Backend for tests, served on a test's own event loop.
+#[cfg(test)]
+pub mod testing {
+    use std::os::unix::net::UnixStream;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Instant;
+
+    use calloop::EventLoop;
+    use zbus::zvariant::{ObjectPath, OwnedValue};
+
+    use super::*;
+
+    /// Object path of the fake destination's portal.
+    const DESTINATION_PATH: &str = "/org/freedesktop/portal/desktop";
+
+    /// RemoteDesktop destination starting every session, recording the device
+    /// types it was asked for and whether a session was closed.
+    #[derive(Clone, Default)]
+    pub struct Destination {
+        pub device_types: Arc<Mutex<Option<u32>>>,
+        pub closed: Arc<AtomicBool>,
+        /// Answers `CreateSession` with 2 while set.
+        pub refuse: Arc<AtomicBool>,
+    }
+
+    impl Destination {
+        pub fn closed(&self) -> bool {
+            self.closed.load(Ordering::Relaxed)
+        }
+    }
+
+    #[zbus::interface(name = "org.freedesktop.impl.portal.RemoteDesktop")]
+    impl Destination {
+        async fn create_session(
+            &self,
+            #[zbus(object_server)] server: &zbus::ObjectServer,
+            _handle: ObjectPath<'_>,
+            session_handle: ObjectPath<'_>,
+            _app_id: String,
+            _options: HashMap<String, OwnedValue>,
+        ) -> zbus::fdo::Result<(u32, HashMap<String, OwnedValue>)> {
+            if self.refuse.load(Ordering::Relaxed) {
+                return Ok((2, HashMap::new()));
+            }
+            let session = DownstreamSession {
+                closed: self.closed.clone(),
+            };
+            server.at(session_handle.to_owned(), session).await?;
+            Ok((0, HashMap::new()))
+        }
+
+        async fn select_devices(
+            &self,
+            _handle: ObjectPath<'_>,
+            _session_handle: ObjectPath<'_>,
+            _app_id: String,
+            options: HashMap<String, OwnedValue>,
+        ) -> (u32, HashMap<String, OwnedValue>) {
+            *self.device_types.lock().unwrap() = options
+                .get("types")
+                .and_then(|types| u32::try_from(types).ok());
+            (0, HashMap::new())
+        }
+
+        async fn start(
+            &self,
+            _handle: ObjectPath<'_>,
+            _session_handle: ObjectPath<'_>,
+            _app_id: String,
+            _parent_window: String,
+            _options: HashMap<String, OwnedValue>,
+        ) -> (u32, HashMap<String, OwnedValue>) {
+            (
+                0,
+                HashMap::from([("devices".to_string(), OwnedValue::from(1u32))]),
+            )
+        }
+    }
+
+    struct DownstreamSession {
+        closed: Arc<AtomicBool>,
+    }
+
+    #[zbus::interface(name = "org.freedesktop.impl.portal.Session")]
+    impl DownstreamSession {
+        fn close(&self) {
+            self.closed.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Backend proxying RemoteDesktop to a [`Destination`] served by its peer.
+    pub fn proxied(
+        event_loop: &EventLoop<'static, XdgBypass>,
+        mut config: XdgBypassConfig,
+    ) -> (XdgBypass, zbus::Connection, Destination) {
+        config.interfaces.insert(
+            Interface::RemoteDesktop,
+            vec![WorkingMode::Proxy(ProxyDestination::new(
+                "org.example.Destination".to_string(),
+                ObjectPath::try_from(DESTINATION_PATH).unwrap().into(),
+            ))],
+        );
+        let destination = Destination::default();
+        let served = destination.clone();
+        let (xdg_bypass, peer) = xdg_bypass_with_peer(event_loop, config, move |stream| {
+            zbus::connection::Builder::unix_stream(stream)
+                .serve_at(DESTINATION_PATH, served)
+                .unwrap()
+        });
+        (xdg_bypass, peer, destination)
+    }
+
+    /// Request handle of the call named `token`.
+    pub fn handle(token: &str) -> ObjectPath<'static> {
+        ObjectPath::try_from(format!("/org/freedesktop/portal/desktop/request/1/{token}")).unwrap()
+    }
+
+    /// Session handle of the session named `token`.
+    pub fn session(token: &str) -> OwnedObjectPath {
+        OwnedObjectPath::try_from(format!("/org/freedesktop/portal/desktop/session/1/{token}"))
+            .unwrap()
+    }
+
+    /// Backend running on `event_loop`, its connection talks to a peer that
+    /// answers nothing.
+    pub fn xdg_bypass(
+        event_loop: &EventLoop<'static, XdgBypass>,
+        config: XdgBypassConfig,
+    ) -> XdgBypass {
+        xdg_bypass_with_peer(event_loop, config, zbus::connection::Builder::unix_stream).0
+    }
+
+    /// Backend running on `event_loop`, its connection talks to the peer
+    /// `peer` builds from the other end of the socket, e.g. with objects
+    /// standing in for a proxy destination.
+    pub fn xdg_bypass_with_peer(
+        event_loop: &EventLoop<'static, XdgBypass>,
+        config: XdgBypassConfig,
+        peer: impl FnOnce(UnixStream) -> zbus::connection::Builder<'static>,
+    ) -> (XdgBypass, zbus::Connection) {
+        let (executor, scheduler) = calloop::futures::executor().unwrap();
+        event_loop
+            .handle()
+            .insert_source(executor, |_, _, _| {})
+            .unwrap();
+
+        let (server, client) = UnixStream::pair().unwrap();
+        let (peer, connection) = futures::executor::block_on(futures::future::join(
+            peer(server)
+                .server(zbus::Guid::generate())
+                .unwrap()
+                .p2p()
+                .build(),
+            zbus::connection::Builder::unix_stream(client).p2p().build(),
+        ));
+
+        let xdg_bypass = XdgBypass::new(
+            config,
+            event_loop.get_signal(),
+            event_loop.handle(),
+            scheduler,
+            connection.unwrap(),
+            None,
+        );
+        (xdg_bypass, peer.unwrap())
+    }
+
+    /// Hand `event` of `session` to the backend and run the loop until it is
+    /// answered, `None` when it is dropped unanswered.
+    pub fn request(
+        event_loop: &mut EventLoop<'static, XdgBypass>,
+        xdg_bypass: &mut XdgBypass,
+        session: &OwnedObjectPath,
+        event: Event,
+    ) -> Option<EventResponse> {
+        let (return_tx, mut return_rx) = oneshot::channel();
+        xdg_bypass.handle(EventHandle {
+            session: session.clone(),
+            event,
+            return_tx,
+        });
+        let mut response = None;
+        run_until(event_loop, xdg_bypass, |_| match return_rx.try_recv() {
+            Ok(None) => false,
+            Ok(answer) => {
+                response = answer;
+                true
+            }
+            Err(_) => true,
+        });
+        response
+    }
+
+    /// Run the loop until `done` holds, panicking after five seconds.
+    pub fn run_until(
+        event_loop: &mut EventLoop<'static, XdgBypass>,
+        xdg_bypass: &mut XdgBypass,
+        mut done: impl FnMut(&mut XdgBypass) -> bool,
+    ) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !done(xdg_bypass) {
+            assert!(
+                Instant::now() < deadline,
+                "timed out running the event loop"
+            );
+            event_loop
+                .dispatch(Duration::from_millis(10), xdg_bypass)
+                .unwrap();
+        }
+    }
+}
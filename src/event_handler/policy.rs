@@ -0,0 +1,410 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use calloop::RegistrationToken;
+use calloop::timer::{TimeoutAction, Timer};
+use futures::channel::oneshot;
+use tracing::{error, info};
+use zbus::zvariant::{OwnedObjectPath, OwnedValue};
+
+use crate::event_handler::audit::ClosedBy;
+use crate::event_handler::events::remote_desktop::RemoteDesktopEvent;
+use crate::event_handler::events::screen_cast::ScreenCastEvent;
+use crate::event_handler::{
+    Event, EventHandle, EventResponse, Interface, XdgBypass, empty_results, return_response,
+};
+
+/// Every device type of the portal: keyboard (1), pointer (2) and touchscreen (4).
+pub const DEVICE_TYPES: u32 = 7;
+
+/// What a matching app may do with RemoteDesktop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Access {
+    /// Sessions start without asking the user.
+    Allow,
+    /// The user approves every `Start`, as without a policy.
+    Prompt,
+    /// Sessions are refused on `CreateSession`.
+    Deny,
+}
+
+/// Policy of the RemoteDesktop sessions of the apps matching `app_id`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PolicyRule {
+    /// App id the rule applies to. A trailing `*` matches every app id
+    /// starting with the rest, `*` alone matches every app.
+    pub app_id: String,
+    pub access: Access,
+    /// Bitmask of the device types the app may select.
+    pub device_types: u32,
+    /// Whether the app may select ScreenCast sources on its sessions.
+    pub streams: bool,
+    /// Time after a successful `Start` when sessions are closed, `None` keeps
+    /// them open.
+    pub max_duration: Option<Duration>,
+}
+
+impl Default for PolicyRule {
+    fn default() -> Self {
+        Self {
+            app_id: "*".to_string(),
+            access: Access::Prompt,
+            device_types: DEVICE_TYPES,
+            streams: true,
+            max_duration: None,
+        }
+    }
+}
+
+impl PolicyRule {
+    pub fn matches(&self, app_id: &str) -> bool {
+        match self.app_id.strip_suffix('*') {
+            Some(prefix) => app_id.starts_with(prefix),
+            None => app_id == self.app_id,
+        }
+    }
+}
+
+/// Rules of the running RemoteDesktop sessions, picked on `CreateSession` so a
+/// reload never changes them.
+#[derive(Default)]
+pub struct SessionPolicies {
+    sessions: HashMap<OwnedObjectPath, SessionPolicy>,
+}
+
+impl SessionPolicies {
+    /// Rule of `session`, `None` for sessions other than RemoteDesktop.
+    pub fn rule(&self, session: &OwnedObjectPath) -> Option<&PolicyRule> {
+        self.sessions.get(session).map(|policy| &policy.rule)
+    }
+}
+
+struct SessionPolicy {
+    rule: PolicyRule,
+    /// Timer closing the session after `max_duration`.
+    deadline: Option<RegistrationToken>,
+}
+
+/// Apply the policy to `event` before the session's handler sees it.
+///
+/// Returns the event to hand on, with its options limited to what the rule
+/// allows, or `None` when the policy answered it already.
+pub fn apply(xdg_bypass: &mut XdgBypass, mut event: EventHandle) -> Option<EventHandle> {
+    if let Event::CreateSession(create_session) = &event.event {
+        if create_session.interface != Interface::RemoteDesktop {
+            return Some(event);
+        }
+        let rule = xdg_bypass.config.policy_rule(&create_session.app_id);
+        if rule.access == Access::Deny {
+            info!(
+                "[Policy] RemoteDesktop session of {} denied by rule {}",
+                create_session.app_id, rule.app_id
+            );
+            return_response(
+                event.return_tx,
+                EventResponse::Standard(2, empty_results()),
+                "Policy.CreateSession",
+            );
+            return None;
+        }
+        xdg_bypass.policies.sessions.insert(
+            event.session.clone(),
+            SessionPolicy {
+                rule,
+                deadline: None,
+            },
+        );
+        return Some(event);
+    }
+
+    let Some(policy) = xdg_bypass.policies.sessions.get(&event.session) else {
+        return Some(event);
+    };
+    let rule = &policy.rule;
+    match &mut event.event {
+        Event::RemoteDesktop(RemoteDesktopEvent::SelectDevices(select_devices)) => {
+            if rule.device_types == DEVICE_TYPES {
+                return Some(event);
+            }
+            // Malformed or unknown types are left to the handler to reject.
+            let requested = match select_devices.options.get("types") {
+                Some(types) => match u32::try_from(types.clone()) {
+                    Ok(types) if types & !DEVICE_TYPES == 0 => types,
+                    _ => return Some(event),
+                },
+                None => DEVICE_TYPES,
+            };
+            let allowed = requested & rule.device_types;
+            if allowed == 0 {
+                info!(
+                    "[Policy] {} may not use device types {}",
+                    select_devices.app_id, requested
+                );
+                return_response(
+                    event.return_tx,
+                    EventResponse::Standard(2, empty_results()),
+                    "Policy.SelectDevices",
+                );
+                return None;
+            }
+            if allowed != requested {
+                info!(
+                    "[Policy] Device types of {} limited from {} to {}",
+                    select_devices.app_id, requested, allowed
+                );
+            }
+            select_devices
+                .options
+                .insert("types".to_string(), OwnedValue::from(allowed));
+        }
+        Event::RemoteDesktop(RemoteDesktopEvent::Start(_)) => {
+            if let (Some(duration), None) = (rule.max_duration, policy.deadline) {
+                return limit_duration(xdg_bypass, event, duration);
+            }
+        }
+        Event::ScreenCast(ScreenCastEvent::SelectSources(select_sources)) if !rule.streams => {
+            info!(
+                "[Policy] {} may not select ScreenCast sources",
+                select_sources.app_id
+            );
+            return_response(
+                event.return_tx,
+                EventResponse::Standard(2, empty_results()),
+                "Policy.SelectSources",
+            );
+            return None;
+        }
+        _ => {}
+    }
+    Some(event)
+}
+
+/// Drop the rule of a session that ended, along with its timer.
+pub fn forget(xdg_bypass: &mut XdgBypass, session: &OwnedObjectPath) {
+    if let Some(SessionPolicy {
+        deadline: Some(deadline),
+        ..
+    }) = xdg_bypass.policies.sessions.remove(session)
+    {
+        xdg_bypass.loop_handle.remove(deadline);
+    }
+}
+
+/// Hand on a `Start`, arming the session's timer once it answers successfully.
+fn limit_duration(
+    xdg_bypass: &mut XdgBypass,
+    event: EventHandle,
+    duration: Duration,
+) -> Option<EventHandle> {
+    let EventHandle {
+        session,
+        event,
+        return_tx: caller,
+    } = event;
+
+    let (return_tx, return_rx) = oneshot::channel();
+    let started = session.clone();
+    // A handler dropping the reply drops the caller's as well.
+    let scheduled = xdg_bypass.schedule_then(return_rx, move |xdg_bypass, response| {
+        let Ok(response) = response else {
+            return;
+        };
+        if let EventResponse::Standard(0, _) = response {
+            let deadline = close_after(xdg_bypass, started.clone(), duration);
+            match xdg_bypass.policies.sessions.get_mut(&started) {
+                Some(policy) => policy.deadline = deadline,
+                // Closed before it started.
+                None => {
+                    if let Some(deadline) = deadline {
+                        xdg_bypass.loop_handle.remove(deadline);
+                    }
+                }
+            }
+        }
+        return_response(caller, response, "Policy.Start");
+    });
+    if let Err(e) = scheduled {
+        error!("[Policy] Failed to wait for the session to start: {:#}", e);
+    }
+
+    Some(EventHandle {
+        session,
+        event,
+        return_tx,
+    })
+}
+
+fn close_after(
+    xdg_bypass: &mut XdgBypass,
+    session: OwnedObjectPath,
+    duration: Duration,
+) -> Option<RegistrationToken> {
+    xdg_bypass
+        .loop_handle
+        .insert_source(Timer::from_duration(duration), move |_, _, xdg_bypass| {
+            // The timer drops itself, closing must not remove it again.
+            if let Some(policy) = xdg_bypass.policies.sessions.get_mut(&session) {
+                policy.deadline = None;
+            }
+            if xdg_bypass.sessions.contains_key(&session) {
+                info!(
+                    "[Policy] Session {} reached its maximum duration of {:?}",
+                    session, duration
+                );
+                xdg_bypass.close_session(&session, ClosedBy::MaxDuration);
+            }
+            TimeoutAction::Drop
+        })
+        .map_err(|e| error!("[Policy] Failed to limit the session duration: {}", e))
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use calloop::EventLoop;
+
+    use super::*;
+    use crate::event_handler::events::remote_desktop::{SelectDevices, Start};
+    use crate::event_handler::{CreateSession, XdgBypassConfig, testing};
+
+    const APP_ID: &str = "org.example.App";
+
+    fn config(rule: PolicyRule) -> XdgBypassConfig {
+        let mut config = XdgBypassConfig::default();
+        config.policy.push(rule);
+        config
+    }
+
+    fn create_session() -> Event {
+        Event::CreateSession(CreateSession {
+            interface: Interface::RemoteDesktop,
+            handle: testing::handle("create"),
+            app_id: APP_ID.to_string(),
+            options: HashMap::new(),
+        })
+    }
+
+    fn select_devices(types: u32) -> Event {
+        Event::RemoteDesktop(RemoteDesktopEvent::SelectDevices(SelectDevices {
+            handle: testing::handle("select"),
+            app_id: APP_ID.to_string(),
+            options: HashMap::from([("types".to_string(), OwnedValue::from(types))]),
+        }))
+    }
+
+    fn start() -> Event {
+        Event::RemoteDesktop(RemoteDesktopEvent::Start(Start {
+            handle: testing::handle("start"),
+            app_id: APP_ID.to_string(),
+            parent_window: String::new(),
+            options: HashMap::new(),
+        }))
+    }
+
+    fn code(response: Option<EventResponse>) -> Option<u32> {
+        match response {
+            Some(EventResponse::Standard(code, _)) => Some(code),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn denied_sessions_are_refused() {
+        let mut event_loop = EventLoop::try_new().unwrap();
+        let mut xdg_bypass = testing::xdg_bypass(
+            &event_loop,
+            config(PolicyRule {
+                access: Access::Deny,
+                ..PolicyRule::default()
+            }),
+        );
+        let session = testing::session("denied");
+
+        let created =
+            testing::request(&mut event_loop, &mut xdg_bypass, &session, create_session());
+        assert_eq!(code(created), Some(2));
+        assert!(!xdg_bypass.sessions.contains_key(&session));
+        assert!(xdg_bypass.policies.rule(&session).is_none());
+    }
+
+    #[test]
+    fn device_types_are_limited_to_the_rule() {
+        let mut event_loop = EventLoop::try_new().unwrap();
+        let (mut xdg_bypass, _peer, destination) = testing::proxied(
+            &event_loop,
+            config(PolicyRule {
+                device_types: 1,
+                ..PolicyRule::default()
+            }),
+        );
+        let session = testing::session("limited");
+        let created =
+            testing::request(&mut event_loop, &mut xdg_bypass, &session, create_session());
+        assert_eq!(code(created), Some(0));
+
+        let selected = testing::request(
+            &mut event_loop,
+            &mut xdg_bypass,
+            &session,
+            select_devices(3),
+        );
+        assert_eq!(code(selected), Some(0));
+        assert_eq!(*destination.device_types.lock().unwrap(), Some(1));
+
+        *destination.device_types.lock().unwrap() = None;
+        let selected = testing::request(
+            &mut event_loop,
+            &mut xdg_bypass,
+            &session,
+            select_devices(4),
+        );
+        assert_eq!(code(selected), Some(2));
+        assert_eq!(*destination.device_types.lock().unwrap(), None);
+    }
+
+    #[test]
+    fn deadline_runs_from_start() {
+        let mut event_loop = EventLoop::try_new().unwrap();
+        let (mut xdg_bypass, _peer, destination) = testing::proxied(
+            &event_loop,
+            config(PolicyRule {
+                max_duration: Some(Duration::from_millis(100)),
+                ..PolicyRule::default()
+            }),
+        );
+        let session = testing::session("limited");
+        let created =
+            testing::request(&mut event_loop, &mut xdg_bypass, &session, create_session());
+        assert_eq!(code(created), Some(0));
+
+        // Longer than the limit, yet the session hasn't started.
+        let waited = Instant::now() + Duration::from_millis(300);
+        testing::run_until(&mut event_loop, &mut xdg_bypass, |_| {
+            Instant::now() > waited
+        });
+        assert!(xdg_bypass.sessions.contains_key(&session));
+
+        let started = testing::request(&mut event_loop, &mut xdg_bypass, &session, start());
+        assert_eq!(code(started), Some(0));
+        assert!(xdg_bypass.sessions.contains_key(&session));
+
+        testing::run_until(&mut event_loop, &mut xdg_bypass, |_| destination.closed());
+        assert!(!xdg_bypass.sessions.contains_key(&session));
+    }
+
+    #[test]
+    fn rules_match_app_ids() {
+        let rule = |app_id: &str| PolicyRule {
+            app_id: app_id.to_string(),
+            ..PolicyRule::default()
+        };
+        assert!(rule("*").matches(""));
+        assert!(rule("org.example.*").matches("org.example.Automation"));
+        assert!(!rule("org.example.*").matches("org.other.App"));
+        assert!(rule("org.example.App").matches("org.example.App"));
+        assert!(!rule("org.example.App").matches("org.example.App2"));
+    }
+}
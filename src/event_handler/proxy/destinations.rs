@@ -0,0 +1,464 @@
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use calloop::channel;
+use futures::StreamExt;
+use tracing::{debug, error, info, warn};
+use zbus::zvariant::OwnedObjectPath;
+
+use crate::event_handler::audit::ClosedBy;
+use crate::event_handler::proxy::{call, remote_desktop, screen_cast};
+use crate::event_handler::{EventHandle, Interface, ProxyDestination, WorkingMode, XdgBypass};
+
+/// Service name, object path and interface a proxy is built for.
+type ProxyKey = (String, OwnedObjectPath, Interface);
+
+/// How long a failed probe is trusted before the destination is probed again.
+const FAILED_PROBE_EXPIRY: Duration = Duration::from_secs(60);
+
+/// Outcome of a destination's probe.
+#[derive(Debug, Clone, Copy)]
+enum Probe {
+    Available,
+    Failed(Instant),
+}
+
+impl Probe {
+    fn new(available: bool) -> Self {
+        match available {
+            true => Probe::Available,
+            false => Probe::Failed(Instant::now()),
+        }
+    }
+
+    /// Whether the destination is available, `None` once a failure expired.
+    fn available(self) -> Option<bool> {
+        match self {
+            Probe::Available => Some(true),
+            Probe::Failed(at) if at.elapsed() < FAILED_PROBE_EXPIRY => Some(false),
+            Probe::Failed(_) => None,
+        }
+    }
+}
+
+/// Backends the proxied interfaces forward to, and the proxies built for them.
+#[derive(Default)]
+pub struct Destinations {
+    /// Service names whose owner is watched.
+    watched: HashSet<String>,
+    /// Proxies shared by the sessions of a destination, dropped when its
+    /// owner goes away so they are built again once it returns.
+    proxies: HashMap<ProxyKey, zbus::Proxy<'static>>,
+    /// Whether a destination answered its probe. Only kept for watched
+    /// destinations, a change of their owner probes them again, and failures
+    /// expire after [`FAILED_PROBE_EXPIRY`].
+    probes: HashMap<ProxyKey, Probe>,
+    /// Counts the owner changes, probes answered by a previous owner are not
+    /// cached.
+    generation: u64,
+}
+
+/// Mode picked by [`select_mode`], along with what it learned on the way.
+struct Selected {
+    generation: u64,
+    mode: WorkingMode,
+    probes: Vec<(ProxyKey, bool)>,
+    proxies: Vec<(ProxyKey, zbus::Proxy<'static>)>,
+}
+
+/// Watch the owner of every configured destination, for the ones not
+/// watched yet, and build the proxies missing for them.
+pub fn watch_destinations(xdg_bypass: &mut XdgBypass) {
+    let service_names: HashSet<String> = Interface::ALL
+        .into_iter()
+        .flat_map(|interface| xdg_bypass.config.proxy_destinations(interface))
+        .map(|destination| destination.service_name().to_string())
+        .collect();
+
+    for service_name in service_names {
+        if xdg_bypass.destinations.watched.contains(&service_name) {
+            continue;
+        }
+        match watch_destination(xdg_bypass, service_name.clone()) {
+            Ok(()) => {
+                xdg_bypass.destinations.watched.insert(service_name);
+            }
+            Err(e) => error!(
+                "[Destinations] Failed to watch destination {}: {:#}",
+                service_name, e
+            ),
+        }
+    }
+
+    let keys = configured_proxies(xdg_bypass);
+    prepare_proxies(xdg_bypass, keys);
+}
+
+/// The proxy of `interface` at `destination`. Proxies are built ahead by
+/// [`select_mode`] and [`watch_destinations`], never on the event loop.
+pub fn destination_proxy(
+    xdg_bypass: &XdgBypass,
+    interface: Interface,
+    destination: &ProxyDestination,
+) -> anyhow::Result<zbus::Proxy<'static>> {
+    xdg_bypass
+        .destinations
+        .proxies
+        .get(&proxy_key(destination, interface))
+        .cloned()
+        .with_context(|| {
+            format!(
+                "The {} proxy of {} is not built yet",
+                interface.name(),
+                destination.service_name()
+            )
+        })
+}
+
+/// Pick the working mode of `interface` for `event`, then continue with
+/// `then` on the event loop.
+///
+/// Modes are tried in order. Every proxy destination but the last mode is
+/// probed first, and skipped when it doesn't answer or offers nothing. Probes
+/// and the proxies the picked mode needs run in a scheduled future unless
+/// they are known already.
+pub fn select_mode(
+    xdg_bypass: &mut XdgBypass,
+    interface: Interface,
+    event: EventHandle,
+    then: fn(&mut XdgBypass, Interface, WorkingMode, EventHandle),
+) {
+    let modes = xdg_bypass.config.modes(interface).to_vec();
+    let last = modes.len() - 1;
+    // Each mode with its cached probe, `None` when it still has to be probed,
+    // and the proxies it needs that are not built yet.
+    let candidates: Vec<(WorkingMode, Option<bool>, Vec<ProxyKey>)> = modes
+        .into_iter()
+        .enumerate()
+        .map(|(index, mode)| {
+            let available = match &mode {
+                WorkingMode::Proxy(destination) if index != last => xdg_bypass
+                    .destinations
+                    .probes
+                    .get(&proxy_key(destination, interface))
+                    .and_then(|probe| probe.available()),
+                _ => Some(true),
+            };
+            let missing = required_proxies(xdg_bypass, interface, &mode)
+                .into_iter()
+                .filter(|key| !xdg_bypass.destinations.proxies.contains_key(key))
+                .collect();
+            (mode, available, missing)
+        })
+        .collect();
+
+    if let Some((mode, Some(true), missing)) = candidates
+        .iter()
+        .find(|(_, available, _)| *available != Some(false))
+        && missing.is_empty()
+    {
+        then(xdg_bypass, interface, mode.clone(), event);
+        return;
+    }
+
+    let generation = xdg_bypass.destinations.generation;
+    let connection = xdg_bypass.connection.clone();
+    let timeout = xdg_bypass.config.proxy.timeout;
+    let selected = async move {
+        let mut probes = Vec::new();
+        for (mode, available, missing) in candidates {
+            if let WorkingMode::Proxy(destination) = &mode {
+                match available {
+                    Some(true) => {}
+                    Some(false) => {
+                        debug!(
+                            "[Destinations] {} failed a recent probe for {}, trying the next mode",
+                            destination.service_name(),
+                            interface.name()
+                        );
+                        continue;
+                    }
+                    None => {
+                        let probed =
+                            call(probe(&connection, interface, destination), timeout).await;
+                        probes.push((proxy_key(destination, interface), probed.is_ok()));
+                        if let Err(e) = probed {
+                            info!(
+                                "[Destinations] {} unavailable for {}, trying the next mode: {}",
+                                destination.service_name(),
+                                interface.name(),
+                                e
+                            );
+                            continue;
+                        }
+                    }
+                }
+            }
+            let proxies = build_proxies(&connection, missing).await;
+            return Some(Selected {
+                generation,
+                mode,
+                probes,
+                proxies,
+            });
+        }
+        None
+    };
+    // The event waits until the probes picked a mode.
+    let scheduled = xdg_bypass.schedule_then(selected, move |xdg_bypass, selected| {
+        let Some(selected) = selected else {
+            return;
+        };
+        if selected.generation == xdg_bypass.destinations.generation {
+            for (key, available) in selected.probes {
+                if xdg_bypass.destinations.watched.contains(&key.0) {
+                    xdg_bypass
+                        .destinations
+                        .probes
+                        .insert(key, Probe::new(available));
+                }
+            }
+        }
+        store_proxies(xdg_bypass, selected.proxies);
+        then(xdg_bypass, interface, selected.mode, event);
+    });
+    if let Err(e) = scheduled {
+        error!(
+            "[Destinations] Failed to probe for {}: {:#}",
+            interface.name(),
+            e
+        );
+    }
+}
+
+/// Check that `destination` serves `interface` and offers at least one
+/// device or source type.
+async fn probe(
+    connection: &zbus::Connection,
+    interface: Interface,
+    destination: &ProxyDestination,
+) -> zbus::fdo::Result<()> {
+    let probed = match interface {
+        Interface::RemoteDesktop => remote_desktop::probe(connection, destination).await,
+        Interface::ScreenCast => screen_cast::probe(connection, destination).await,
+    };
+    // Property getters wrap the destination's error, unwrap it for the log.
+    let (version, available) = probed.map_err(|e| match e {
+        zbus::fdo::Error::ZBus(zbus::Error::FDO(e)) => *e,
+        e => e,
+    })?;
+    debug!(
+        "[Destinations] {} serves {} version {}, available types {}",
+        destination.service_name(),
+        interface.name(),
+        version,
+        available
+    );
+    if available == 0 {
+        return Err(zbus::fdo::Error::NotSupported(
+            "No device or source types available".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn proxy_key(destination: &ProxyDestination, interface: Interface) -> ProxyKey {
+    (
+        destination.service_name().to_string(),
+        destination.object_path().clone(),
+        interface,
+    )
+}
+
+/// Proxies the handlers of `mode` look up when they are created. Proxied
+/// RemoteDesktop sessions forward ScreenCast calls to the same destination,
+/// local ones to the first ScreenCast destination.
+fn required_proxies(
+    xdg_bypass: &XdgBypass,
+    interface: Interface,
+    mode: &WorkingMode,
+) -> Vec<ProxyKey> {
+    match (interface, mode) {
+        (Interface::RemoteDesktop, WorkingMode::Proxy(destination)) => vec![
+            proxy_key(destination, Interface::RemoteDesktop),
+            proxy_key(destination, Interface::ScreenCast),
+        ],
+        (Interface::RemoteDesktop, WorkingMode::Server) => xdg_bypass
+            .config
+            .proxy_destinations(Interface::ScreenCast)
+            .next()
+            .map(|destination| proxy_key(destination, Interface::ScreenCast))
+            .into_iter()
+            .collect(),
+        (Interface::ScreenCast, WorkingMode::Proxy(destination)) => {
+            vec![proxy_key(destination, Interface::ScreenCast)]
+        }
+        (Interface::ScreenCast, WorkingMode::Server) => Vec::new(),
+    }
+}
+
+/// Proxies of every mode of the configuration.
+fn configured_proxies(xdg_bypass: &XdgBypass) -> Vec<ProxyKey> {
+    let mut keys = Vec::new();
+    for interface in Interface::ALL {
+        for mode in xdg_bypass.config.modes(interface) {
+            keys.extend(required_proxies(xdg_bypass, interface, mode));
+        }
+    }
+    keys
+}
+
+/// Build the proxies of `keys` that don't exist yet in a scheduled future.
+fn prepare_proxies(xdg_bypass: &mut XdgBypass, keys: Vec<ProxyKey>) {
+    let keys: Vec<ProxyKey> = keys
+        .into_iter()
+        .filter(|key| !xdg_bypass.destinations.proxies.contains_key(key))
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    if keys.is_empty() {
+        return;
+    }
+
+    let connection = xdg_bypass.connection.clone();
+    let proxies = async move { build_proxies(&connection, keys).await };
+    if let Err(e) = xdg_bypass.schedule_then(proxies, store_proxies) {
+        error!("[Destinations] Failed to build the proxies: {:#}", e);
+    }
+}
+
+async fn build_proxies(
+    connection: &zbus::Connection,
+    keys: Vec<ProxyKey>,
+) -> Vec<(ProxyKey, zbus::Proxy<'static>)> {
+    let mut proxies = Vec::new();
+    for key in keys {
+        let (service_name, object_path, interface) = key.clone();
+        match zbus::Proxy::new(connection, service_name, object_path, interface.name()).await {
+            Ok(proxy) => proxies.push((key, proxy)),
+            Err(e) => error!(
+                "[Destinations] Failed to build the {} proxy of {}: {}",
+                interface.name(),
+                key.0,
+                e
+            ),
+        }
+    }
+    proxies
+}
+
+/// Keep proxies built off the event loop, unless one was stored meanwhile.
+fn store_proxies(xdg_bypass: &mut XdgBypass, proxies: Vec<(ProxyKey, zbus::Proxy<'static>)>) {
+    for (key, proxy) in proxies {
+        xdg_bypass.destinations.proxies.entry(key).or_insert(proxy);
+    }
+}
+
+fn watch_destination(xdg_bypass: &mut XdgBypass, service_name: String) -> anyhow::Result<()> {
+    // `true` once the name got an owner, `false` once it lost it. A restart
+    // replacing the owner directly sends both.
+    let (sender, receiver) = channel::channel::<bool>();
+    let changed_name = service_name.clone();
+    xdg_bypass
+        .loop_handle
+        .insert_source(receiver, move |event, _, xdg_bypass| {
+            if let channel::Event::Msg(online) = event {
+                match online {
+                    true => destination_returned(xdg_bypass, &changed_name),
+                    false => destination_vanished(xdg_bypass, &changed_name),
+                }
+            }
+        })
+        .map_err(|e| anyhow::anyhow!("Failed to insert the owner watch: {}", e))?;
+
+    let connection = xdg_bypass.connection.clone();
+    xdg_bypass
+        .scheduler
+        .schedule(async move {
+            let watched = async {
+                let dbus = zbus::fdo::DBusProxy::new(&connection).await?;
+                let mut changes = dbus
+                    .receive_name_owner_changed_with_args(&[(0, service_name.as_str())])
+                    .await?;
+                debug!("[Destinations] Watching the owner of {}", service_name);
+                while let Some(change) = changes.next().await {
+                    let args = change.args()?;
+                    if args.old_owner().is_some() && sender.send(false).is_err() {
+                        break;
+                    }
+                    if args.new_owner().is_some() && sender.send(true).is_err() {
+                        break;
+                    }
+                }
+                zbus::fdo::Result::Ok(())
+            };
+            if let Err(e) = watched.await {
+                error!(
+                    "[Destinations] Lost the owner watch of {}: {}",
+                    service_name, e
+                );
+            }
+        })
+        .with_context(|| "Failed to schedule the owner watch")
+}
+
+/// Close every session forwarding to `service_name`, the backend and its
+/// sessions are gone.
+fn destination_vanished(xdg_bypass: &mut XdgBypass, service_name: &str) {
+    forget_destination(xdg_bypass, service_name);
+
+    let sessions: Vec<OwnedObjectPath> = xdg_bypass
+        .sessions
+        .iter()
+        .filter(|(_, handler)| handler.uses_destination(service_name))
+        .map(|(session, _)| session.clone())
+        .collect();
+    warn!(
+        "[Destinations] {} left the bus, closing {} sessions",
+        service_name,
+        sessions.len()
+    );
+    for session in sessions {
+        xdg_bypass.close_session(&session, ClosedBy::DestinationExited);
+    }
+}
+
+/// Build the proxies of the interfaces configured for `service_name` again.
+fn destination_returned(xdg_bypass: &mut XdgBypass, service_name: &str) {
+    info!("[Destinations] {} is on the bus", service_name);
+    // Proxies built while the name had no owner may hold a failed property
+    // cache, and a new owner may answer probes differently.
+    forget_destination(xdg_bypass, service_name);
+
+    let keys = configured_proxies(xdg_bypass)
+        .into_iter()
+        .filter(|(name, _, _)| name == service_name)
+        .collect();
+    prepare_proxies(xdg_bypass, keys);
+}
+
+fn forget_destination(xdg_bypass: &mut XdgBypass, service_name: &str) {
+    xdg_bypass.destinations.generation += 1;
+    xdg_bypass
+        .destinations
+        .proxies
+        .retain(|(name, _, _), _| name != service_name);
+    xdg_bypass
+        .destinations
+        .probes
+        .retain(|(name, _, _), _| name != service_name);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_probes_expire() {
+        assert_eq!(Probe::new(true).available(), Some(true));
+        assert_eq!(Probe::new(false).available(), Some(false));
+        let expired = Instant::now() - FAILED_PROBE_EXPIRY;
+        assert_eq!(Probe::Failed(expired).available(), None);
+    }
+}
@@ -1,2 +1,381 @@
-mod remote_desktop;
-mod screen_cast;
+use std::collections::HashMap;
+use std::pin::pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use anyhow::Context;
+use async_io::Timer;
+use futures::StreamExt;
+use futures::channel::oneshot;
+use futures::future::{AbortHandle, Aborted, Either, abortable, select};
+use tracing::{debug, error, info};
+use zbus::proxy;
+use zbus::zvariant::{self, ObjectPath, OwnedValue};
+
+use crate::event_handler::audit::ClosedBy;
+use crate::event_handler::{EventResponse, ProxyOptions, XdgBypass, empty_results};
+
+pub mod destinations;
+pub mod remote_desktop;
+pub mod screen_cast;
+
+#[proxy(interface = "org.freedesktop.impl.portal.Request")]
+trait RequestProxySenderTrait {
+    fn close(&self) -> zbus::fdo::Result<()>;
+}
+
+#[proxy(interface = "org.freedesktop.impl.portal.Session")]
+trait SessionProxySenderTrait {
+    fn close(&self) -> zbus::fdo::Result<()>;
+
+    #[zbus(signal)]
+    fn closed(&self) -> zbus::Result<()>;
+}
+
+/// Object paths a proxied session uses at its destination.
+///
+/// The destination exports its own Request and Session objects at these, so
+/// they never collide with the objects the frontend knows on our side.
+#[derive(Debug, Clone)]
+pub struct DownstreamPaths {
+    id: u64,
+}
+
+impl DownstreamPaths {
+    pub fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+        Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    pub fn session_handle(&self) -> ObjectPath<'static> {
+        ObjectPath::try_from(format!(
+            "/org/freedesktop/portal/desktop/session/bypass/s{}",
+            self.id
+        ))
+        .expect("session handle is a valid object path")
+    }
+
+    /// Downstream handle of the frontend request `handle`. The frontend's token
+    /// is kept, so `Request.Close` finds the same path again.
+    pub fn request_handle(&self, handle: &ObjectPath<'_>) -> ObjectPath<'static> {
+        let token = handle.as_str().rsplit('/').next().unwrap_or_default();
+        ObjectPath::try_from(format!(
+            "/org/freedesktop/portal/desktop/request/bypass/s{}/{}",
+            self.id, token
+        ))
+        .expect("request handle is a valid object path")
+    }
+
+    /// Downstream handle of a request the frontend doesn't know of, e.g. the
+    /// `CreateSession` of a combined session. Every call gets a path of its
+    /// own, below the ones of the frontend's tokens.
+    pub fn own_request_handle(&self) -> ObjectPath<'static> {
+        static NEXT_REQUEST: AtomicU64 = AtomicU64::new(1);
+        ObjectPath::try_from(format!(
+            "/org/freedesktop/portal/desktop/request/bypass/s{}/own/r{}",
+            self.id,
+            NEXT_REQUEST.fetch_add(1, Ordering::Relaxed)
+        ))
+        .expect("request handle is a valid object path")
+    }
+}
+
+/// Close `session` on our side once the destination emits `Closed` for its
+/// downstream session. Abort the returned handle when the session ends first.
+pub fn watch_closed(
+    xdg_bypass: &mut XdgBypass,
+    destination: &zbus::Proxy<'static>,
+    paths: &DownstreamPaths,
+    session: zvariant::OwnedObjectPath,
+) -> anyhow::Result<AbortHandle> {
+    let connection = destination.connection().clone();
+    let service_name = destination.destination().to_owned();
+    let session_handle = paths.session_handle();
+    let closed = async move {
+        let downstream = SessionProxySenderTraitProxy::builder(&connection)
+            .destination(service_name)?
+            .path(session_handle)?
+            .build()
+            .await?;
+        let mut closed = downstream.receive_closed().await?;
+        closed.next().await;
+        zbus::Result::Ok(())
+    };
+    let (closed, abort_handle) = abortable(closed);
+    xdg_bypass
+        .schedule_then(closed, move |xdg_bypass, closed| match closed {
+            Ok(Ok(())) => {
+                info!("[Proxy] Destination closed session {}, closing it", session);
+                xdg_bypass.close_session(&session, ClosedBy::Destination);
+            }
+            Ok(Err(e)) => error!(
+                "[Proxy] Failed to watch the downstream session of {}: {}",
+                session, e
+            ),
+            // The session ended first.
+            Err(Aborted) => {}
+        })
+        .with_context(|| "Failed to watch the downstream session")?;
+
+    Ok(abort_handle)
+}
+
+/// Relay `Session.Close` to the downstream session.
+pub fn close_downstream(
+    xdg_bypass: &mut XdgBypass,
+    destination: &zbus::Proxy<'static>,
+    paths: &DownstreamPaths,
+) -> anyhow::Result<()> {
+    let connection = destination.connection().clone();
+    let service_name = destination.destination().to_owned();
+    let session_handle = paths.session_handle();
+    xdg_bypass
+        .scheduler
+        .schedule(async move {
+            let closed = async {
+                SessionProxySenderTraitProxy::builder(&connection)
+                    .destination(service_name)?
+                    .path(session_handle.clone())?
+                    .build()
+                    .await?
+                    .close()
+                    .await?;
+                zbus::Result::Ok(())
+            };
+            match closed.await {
+                Ok(()) => debug!("[Proxy] Closed downstream session {}", session_handle),
+                Err(e) => error!(
+                    "[Proxy] Failed to close downstream session {}: {}",
+                    session_handle, e
+                ),
+            }
+        })
+        .with_context(|| "Failed to schedule the downstream session close")
+}
+
+/// Await a call to the destination, failing it once `timeout` has passed.
+pub async fn call<T>(
+    call: impl Future<Output = zbus::fdo::Result<T>>,
+    timeout: Option<Duration>,
+) -> zbus::fdo::Result<T> {
+    let Some(timeout) = timeout else {
+        return call.await;
+    };
+
+    match select(pin!(call), pin!(Timer::after(timeout))).await {
+        Either::Left((result, _)) => result,
+        Either::Right(_) => Err(zbus::fdo::Error::TimedOut(format!(
+            "No answer within {} seconds",
+            timeout.as_secs_f64()
+        ))),
+    }
+}
+
+/// Forwards the calls of one session to its destination on the event loop's
+/// executor. Each call is answered with the destination's reply, or as failed
+/// when it errors or runs out of time, which is logged with the session.
+pub struct Forward {
+    session: zvariant::OwnedObjectPath,
+    options: ProxyOptions,
+}
+
+impl Forward {
+    pub fn new(session: zvariant::OwnedObjectPath, options: ProxyOptions) -> Self {
+        Self { session, options }
+    }
+
+    /// Forward a request style call, answered with response code 2 and empty
+    /// results when it fails.
+    pub fn standard(
+        &self,
+        xdg_bypass: &XdgBypass,
+        return_tx: oneshot::Sender<EventResponse>,
+        module: &'static str,
+        call: impl Future<Output = zbus::fdo::Result<(u32, HashMap<String, OwnedValue>)>> + 'static,
+    ) -> anyhow::Result<()> {
+        self.schedule(
+            xdg_bypass,
+            self.options.timeout,
+            call,
+            move |result, session| reply_standard(return_tx, result, module, session),
+        )
+    }
+
+    /// Forward `Start`. It usually waits for the user, so it has its own limit.
+    pub fn start(
+        &self,
+        xdg_bypass: &XdgBypass,
+        return_tx: oneshot::Sender<EventResponse>,
+        module: &'static str,
+        call: impl Future<Output = zbus::fdo::Result<(u32, HashMap<String, OwnedValue>)>> + 'static,
+    ) -> anyhow::Result<()> {
+        self.schedule(
+            xdg_bypass,
+            self.options.start_timeout,
+            call,
+            move |result, session| reply_standard(return_tx, result, module, session),
+        )
+    }
+
+    /// Forward a call without results, such as the `Notify*` methods.
+    pub fn empty(
+        &self,
+        xdg_bypass: &XdgBypass,
+        return_tx: oneshot::Sender<EventResponse>,
+        module: &'static str,
+        call: impl Future<Output = zbus::fdo::Result<()>> + 'static,
+    ) -> anyhow::Result<()> {
+        self.schedule(
+            xdg_bypass,
+            self.options.timeout,
+            call,
+            move |result, session| {
+                reply_standard(
+                    return_tx,
+                    result.map(|()| (0, HashMap::new())),
+                    module,
+                    session,
+                )
+            },
+        )
+    }
+
+    /// Forward a call returning a file descriptor. On failure the sender is
+    /// dropped, which fails the call on the listener side.
+    pub fn fd(
+        &self,
+        xdg_bypass: &XdgBypass,
+        return_tx: oneshot::Sender<EventResponse>,
+        module: &'static str,
+        call: impl Future<Output = zbus::fdo::Result<zvariant::OwnedFd>> + 'static,
+    ) -> anyhow::Result<()> {
+        self.schedule(
+            xdg_bypass,
+            self.options.timeout,
+            call,
+            move |result, session| match result {
+                Ok(fd) => {
+                    let _ = return_tx.send(EventResponse::Fd(fd));
+                }
+                Err(e) => error!("[{}] Session {}: {}", module, session, e),
+            },
+        )
+    }
+
+    /// Forward a property getter. On failure the sender is dropped, which
+    /// fails the property read on the listener side.
+    pub fn value<T: Into<OwnedValue> + 'static>(
+        &self,
+        xdg_bypass: &XdgBypass,
+        return_tx: oneshot::Sender<EventResponse>,
+        module: &'static str,
+        call: impl Future<Output = zbus::fdo::Result<T>> + 'static,
+    ) -> anyhow::Result<()> {
+        self.schedule(
+            xdg_bypass,
+            self.options.timeout,
+            call,
+            move |result, _| match result {
+                Ok(value) => {
+                    let _ = return_tx.send(EventResponse::Value(value.into()));
+                }
+                Err(e) => error!("[{}] {}", module, e),
+            },
+        )
+    }
+
+    /// Close the pending request `handle` at `destination`. The destination
+    /// answers its pending call with response code 1, which then flows back
+    /// through that call's own forward.
+    pub fn close_request(
+        &self,
+        xdg_bypass: &XdgBypass,
+        return_tx: oneshot::Sender<EventResponse>,
+        module: &'static str,
+        destination: &zbus::Proxy<'static>,
+        handle: ObjectPath<'static>,
+    ) -> anyhow::Result<()> {
+        let connection = destination.connection().clone();
+        let service_name = destination.destination().to_owned();
+        let close = async move {
+            RequestProxySenderTraitProxy::builder(&connection)
+                .destination(service_name)?
+                .path(handle)?
+                .build()
+                .await?
+                .close()
+                .await
+        };
+        self.empty(xdg_bypass, return_tx, module, close)
+    }
+
+    fn schedule<T>(
+        &self,
+        xdg_bypass: &XdgBypass,
+        timeout: Option<Duration>,
+        forwarded: impl Future<Output = zbus::fdo::Result<T>> + 'static,
+        reply: impl FnOnce(zbus::fdo::Result<T>, &zvariant::OwnedObjectPath) + 'static,
+    ) -> anyhow::Result<()> {
+        let session = self.session.clone();
+        xdg_bypass
+            .scheduler
+            .schedule(async move {
+                let result = call(forwarded, timeout).await;
+                reply(result, &session);
+            })
+            .with_context(|| "Failed to schedule the call to the destination")
+    }
+}
+
+/// Answer a request style call with the destination's answer, or with
+/// response code 2 and empty results when the call failed.
+fn reply_standard(
+    return_tx: oneshot::Sender<EventResponse>,
+    result: zbus::fdo::Result<(u32, HashMap<String, OwnedValue>)>,
+    module: &str,
+    session: &zvariant::OwnedObjectPath,
+) {
+    let response = match result {
+        Ok((response, results)) => EventResponse::Standard(response, OwnedValue::from(results)),
+        Err(e) => {
+            error!("[{}] Session {}: {}", module, session, e);
+            EventResponse::Standard(2, empty_results())
+        }
+    };
+    let _ = return_tx.send(response);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn calls_time_out() {
+        let result = futures::executor::block_on(call(
+            futures::future::pending::<zbus::fdo::Result<()>>(),
+            Some(Duration::from_millis(10)),
+        ));
+        assert!(matches!(result, Err(zbus::fdo::Error::TimedOut(_))));
+
+        let result = futures::executor::block_on(call(async { Ok(7) }, None));
+        assert_eq!(result, Ok(7));
+    }
+
+    #[test]
+    fn request_handles_keep_the_token() {
+        let paths = DownstreamPaths::new();
+        let handle =
+            ObjectPath::try_from("/org/freedesktop/portal/desktop/request/1_42/t7").unwrap();
+        let downstream = paths.request_handle(&handle);
+        assert!(downstream.as_str().ends_with("/t7"));
+        assert_eq!(downstream, paths.request_handle(&handle));
+        assert_ne!(paths.own_request_handle(), downstream);
+        assert_ne!(paths.own_request_handle(), paths.own_request_handle());
+        assert_ne!(
+            paths.session_handle(),
+            DownstreamPaths::new().session_handle()
+        );
+    }
+}
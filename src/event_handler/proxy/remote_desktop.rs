@@ -4,9 +4,13 @@ use anyhow::Context;
 use zbus::zvariant::{ObjectPath, OwnedValue};
 use zbus::{proxy, zvariant};
 
-use crate::event_handler::EventHandler;
+use crate::event_handler::events::remote_desktop::RemoteDesktopEvent;
+use crate::event_handler::proxy::destinations::destination_proxy;
+use crate::event_handler::proxy::screen_cast::ScreenCastProxy;
+use crate::event_handler::proxy::{DownstreamPaths, Forward, close_downstream, watch_closed};
+use crate::event_handler::{EventHandler, ProxyOptions};
 
-#[proxy(interface = "org.freedesktop.portal.RemoteDesktop")]
+#[proxy(interface = "org.freedesktop.impl.portal.RemoteDesktop")]
 trait RemoteDesktopProxySenderTrait {
     #[zbus(property)]
     fn available_device_types(&self) -> zbus::fdo::Result<u32>;
@@ -131,10 +135,68 @@ trait RemoteDesktopProxySenderTrait {
     ) -> zbus::fdo::Result<zbus::zvariant::OwnedFd>;
 }
 
-#[allow(dead_code)]
+/// Version and available device types of the RemoteDesktop backend at `destination`,
+/// read without caching.
+pub async fn probe(
+    connection: &zbus::Connection,
+    destination: &crate::event_handler::ProxyDestination,
+) -> zbus::fdo::Result<(u32, u32)> {
+    let proxy = RemoteDesktopProxySenderTraitProxy::builder(connection)
+        .destination(destination.service_name())?
+        .path(destination.object_path())?
+        .cache_properties(zbus::proxy::CacheProperties::No)
+        .build()
+        .await?;
+    Ok((
+        proxy.version().await?,
+        proxy.available_device_types().await?,
+    ))
+}
+
 pub struct RemoteDesktopProxy {
     proxyed_session_handle: zvariant::OwnedObjectPath,
     proxy: RemoteDesktopProxySenderTraitProxy<'static>,
+    /// Paths of the session at the destination.
+    paths: DownstreamPaths,
+    /// Proxy options of the configuration the session was created with.
+    options: ProxyOptions,
+    /// Watch of the downstream `Closed` signal, running while the downstream
+    /// session is open.
+    closed_watch: Option<futures::future::AbortHandle>,
+    /// ScreenCast calls on this session, sent to the same destination.
+    screen_cast: Option<ScreenCastProxy>,
+}
+
+impl RemoteDesktopProxy {
+    /// Proxy the RemoteDesktop calls of `session` to `destination`.
+    pub fn with_destination(
+        xdg_bypass: &mut crate::event_handler::XdgBypass,
+        session: zvariant::OwnedObjectPath,
+        destination: &crate::event_handler::ProxyDestination,
+    ) -> anyhow::Result<Self> {
+        let proxy = destination_proxy(
+            xdg_bypass,
+            crate::event_handler::Interface::RemoteDesktop,
+            destination,
+        )
+        .with_context(|| "[RemoteDesktopProxy] Fail to connect to proxy destination.")?;
+        Ok(Self {
+            proxyed_session_handle: session,
+            proxy: proxy.into(),
+            paths: DownstreamPaths::new(),
+            options: xdg_bypass.config.proxy.clone(),
+            closed_watch: None,
+            screen_cast: None,
+        })
+    }
+}
+
+impl Drop for RemoteDesktopProxy {
+    fn drop(&mut self) {
+        if let Some(closed_watch) = self.closed_watch.take() {
+            closed_watch.abort();
+        }
+    }
 }
 
 impl EventHandler for RemoteDesktopProxy {
@@ -143,105 +205,314 @@ impl EventHandler for RemoteDesktopProxy {
         xdg_bypass: &mut crate::event_handler::XdgBypass,
         event: crate::event_handler::EventHandle,
     ) -> anyhow::Result<()> {
+        let this_proxy = self.proxy.clone();
+        let paths = self.paths.clone();
+        let forward = Forward::new(self.proxyed_session_handle.clone(), self.options.clone());
+        let return_tx = event.return_tx;
+
         match event.event {
+            crate::event_handler::Event::CreateSession(create_session) => {
+                self.closed_watch = Some(watch_closed(
+                    xdg_bypass,
+                    self.proxy.inner(),
+                    &self.paths,
+                    self.proxyed_session_handle.clone(),
+                )?);
+                forward.standard(
+                    xdg_bypass,
+                    return_tx,
+                    "RemoteDesktopProxy.CreateSession",
+                    async move {
+                        this_proxy
+                            .create_session(
+                                paths.request_handle(&create_session.handle),
+                                paths.session_handle(),
+                                create_session.app_id,
+                                create_session.options,
+                            )
+                            .await
+                    },
+                )
+            }
+            crate::event_handler::Event::Close => {
+                if let Some(closed_watch) = self.closed_watch.take() {
+                    closed_watch.abort();
+                    close_downstream(xdg_bypass, self.proxy.inner(), &self.paths)?;
+                }
+                let _ = return_tx.send(crate::event_handler::EventResponse::Standard(
+                    0,
+                    crate::event_handler::empty_results(),
+                ));
+                Ok(())
+            }
+            crate::event_handler::Event::CloseRequest(close_request) => forward.close_request(
+                xdg_bypass,
+                return_tx,
+                "RemoteDesktopProxy.CloseRequest",
+                this_proxy.inner(),
+                paths.request_handle(&close_request.handle),
+            ),
+            crate::event_handler::Event::ScreenCast(screen_cast_event) => {
+                // The destination serves the combined session itself, its `Start`
+                // already returns the streams along with the devices.
+                if self.screen_cast.is_none() {
+                    let destination = crate::event_handler::ProxyDestination::new(
+                        self.proxy.inner().destination().to_string(),
+                        self.proxy.inner().path().to_owned().into(),
+                    );
+                    self.screen_cast = Some(ScreenCastProxy::with_destination(
+                        xdg_bypass,
+                        self.proxyed_session_handle.clone(),
+                        &destination,
+                        self.paths.clone(),
+                        true,
+                    )?);
+                }
+                match &mut self.screen_cast {
+                    Some(screen_cast) => screen_cast.handle(
+                        xdg_bypass,
+                        crate::event_handler::EventHandle {
+                            session: event.session,
+                            event: crate::event_handler::Event::ScreenCast(screen_cast_event),
+                            return_tx,
+                        },
+                    ),
+                    None => Ok(()),
+                }
+            }
             crate::event_handler::Event::RemoteDesktop(remote_desktop_event) => {
                 match remote_desktop_event {
-                    crate::event_handler::events::remote_desktop::RemoteDesktopEvent::SelectDevices(select_devices) => {
-                        let this_proxy = self.proxy.clone();
-                        xdg_bypass.scheduler.schedule(async move {
-                            let respone = this_proxy.select_devices(select_devices.handle, select_devices.session_handle, select_devices.app_id, select_devices.options).await.unwrap();
-                            let _ = event.return_tx.send(crate::event_handler::EventResponse::Standard(respone.0, OwnedValue::from(respone.1)));
-                        }).with_context(|| "")?;
-                        Ok(())
-                    },
-                    crate::event_handler::events::remote_desktop::RemoteDesktopEvent::Start(start) => {
-                        let this_proxy = self.proxy.clone();
-                        xdg_bypass.scheduler.schedule(async move {
-                            let response = this_proxy.start(start.handle, start.session_handle, start.app_id, start.parent_window, start.options).await.unwrap();
-                            let _ = event.return_tx.send(crate::event_handler::EventResponse::Standard(response.0, OwnedValue::from(response.1)));
-                        }).with_context(|| "")?;
-                        Ok(())
-                    },
-                    crate::event_handler::events::remote_desktop::RemoteDesktopEvent::NotifyPointerMotion(notify_pointer_motion) => {
-                        let this_proxy = self.proxy.clone();
-                        xdg_bypass.scheduler.schedule(async move {
-                            this_proxy.notify_pointer_motion(notify_pointer_motion.session_handle, notify_pointer_motion.options, notify_pointer_motion.dx, notify_pointer_motion.dy).await.unwrap();
-                            let _ = event.return_tx.send(crate::event_handler::EventResponse::Standard(0, OwnedValue::from(HashMap::<String, OwnedValue>::new())));
-                        }).with_context(|| "")?;
-                        Ok(())
-                    },
-                    crate::event_handler::events::remote_desktop::RemoteDesktopEvent::NotifyPointerMotionAbsolute(notify_pointer_motion_absolute) => {
-                        let this_proxy = self.proxy.clone();
-                        xdg_bypass.scheduler.schedule(async move {
-                            this_proxy.notify_pointer_motion_absolute(notify_pointer_motion_absolute.session_handle, notify_pointer_motion_absolute.options, notify_pointer_motion_absolute.stream, notify_pointer_motion_absolute.x, notify_pointer_motion_absolute.y).await.unwrap();
-                            let _ = event.return_tx.send(crate::event_handler::EventResponse::Standard(0, OwnedValue::from(HashMap::<String, OwnedValue>::new())));
-                        }).with_context(|| "")?;
-                        Ok(())
-                    },
-                    crate::event_handler::events::remote_desktop::RemoteDesktopEvent::NotifyPointerButton(notify_pointer_button) => {
-                        let this_proxy = self.proxy.clone();
-                        xdg_bypass.scheduler.schedule(async move {
-                            this_proxy.notify_pointer_button(notify_pointer_button.session_handle, notify_pointer_button.options, notify_pointer_button.button, notify_pointer_button.state).await.unwrap();
-                            let _ = event.return_tx.send(crate::event_handler::EventResponse::Standard(0, OwnedValue::from(HashMap::<String, OwnedValue>::new())));
-                        }).with_context(|| "")?;
-                        Ok(())
-                    },
-                    crate::event_handler::events::remote_desktop::RemoteDesktopEvent::NotifyPointerAxis(notify_pointer_axis) => {
-                        let this_proxy = self.proxy.clone();
-                        xdg_bypass.scheduler.schedule(async move {
-                            this_proxy.notify_pointer_axis(notify_pointer_axis.session_handle, notify_pointer_axis.options, notify_pointer_axis.dx, notify_pointer_axis.dy).await.unwrap();
-                            let _ = event.return_tx.send(crate::event_handler::EventResponse::Standard(0, OwnedValue::from(HashMap::<String, OwnedValue>::new())));
-                        }).with_context(|| "")?;
-                        Ok(())
-                    },
-                    crate::event_handler::events::remote_desktop::RemoteDesktopEvent::NotifyPointerAxisDiscrete(notify_pointer_axis_discrete) => {
-                        let this_proxy = self.proxy.clone();
-                        xdg_bypass.scheduler.schedule(async move {
-                            this_proxy.notify_pointer_axis_discrete(notify_pointer_axis_discrete.session_handle, notify_pointer_axis_discrete.options, notify_pointer_axis_discrete.axis, notify_pointer_axis_discrete.steps).await.unwrap();
-                            let _ = event.return_tx.send(crate::event_handler::EventResponse::Standard(0, OwnedValue::from(HashMap::<String, OwnedValue>::new())));
-                        }).with_context(|| "")?;
-                        Ok(())
-                    },
-                    crate::event_handler::events::remote_desktop::RemoteDesktopEvent::NotifyKeyboardKeycode(notify_keyboard_keycode) => {
-                        let this_proxy = self.proxy.clone();
-                        xdg_bypass.scheduler.schedule(async move {
-                            this_proxy.notify_keyboard_keycode(notify_keyboard_keycode.session_handle, notify_keyboard_keycode.options, notify_keyboard_keycode.keycode, notify_keyboard_keycode.state).await.unwrap();
-                            let _ = event.return_tx.send(crate::event_handler::EventResponse::Standard(0, OwnedValue::from(HashMap::<String, OwnedValue>::new())));
-                        }).with_context(|| "")?;
-                        Ok(())
-                    },
-                    crate::event_handler::events::remote_desktop::RemoteDesktopEvent::NotifyKeyboardKeysym(notify_keyboard_keysym) => {
-                        let this_proxy = self.proxy.clone();
-                        xdg_bypass.scheduler.schedule(async move {
-                            this_proxy.notify_keyboard_keysym(notify_keyboard_keysym.session_handle, notify_keyboard_keysym.options, notify_keyboard_keysym.keysym, notify_keyboard_keysym.state).await.unwrap();
-                            let _ = event.return_tx.send(crate::event_handler::EventResponse::Standard(0, OwnedValue::from(HashMap::<String, OwnedValue>::new())));
-                        }).with_context(|| "")?;
-                        Ok(())
-                    },
-                    crate::event_handler::events::remote_desktop::RemoteDesktopEvent::GetPropertiesAvilableDeviceTypes => {
-                        let this_proxy = self.proxy.clone();
-                        xdg_bypass.scheduler.schedule(async move {
-                            let response = this_proxy.available_device_types().await.unwrap();
-                            let _ = event.return_tx.send(crate::event_handler::EventResponse::Value(OwnedValue::from(response)));
-                        }).with_context(|| "")?;
-                        Ok(())
-                    },
-                    crate::event_handler::events::remote_desktop::RemoteDesktopEvent::GetPropertiesVersion => {
-                        let this_proxy = self.proxy.clone();
-                        xdg_bypass.scheduler.schedule(async move {
-                            let response = this_proxy.version().await.unwrap();
-                            let _ = event.return_tx.send(crate::event_handler::EventResponse::Value(OwnedValue::from(response)));
-                        }).with_context(|| "")?;
-                        Ok(())
-                    },
+                    RemoteDesktopEvent::SelectDevices(select_devices) => forward.standard(
+                        xdg_bypass,
+                        return_tx,
+                        "RemoteDesktopProxy.SelectDevices",
+                        async move {
+                            this_proxy
+                                .select_devices(
+                                    paths.request_handle(&select_devices.handle),
+                                    paths.session_handle(),
+                                    select_devices.app_id,
+                                    select_devices.options,
+                                )
+                                .await
+                        },
+                    ),
+                    RemoteDesktopEvent::Start(start) => forward.start(
+                        xdg_bypass,
+                        return_tx,
+                        "RemoteDesktopProxy.Start",
+                        async move {
+                            this_proxy
+                                .start(
+                                    paths.request_handle(&start.handle),
+                                    paths.session_handle(),
+                                    start.app_id,
+                                    start.parent_window,
+                                    start.options,
+                                )
+                                .await
+                        },
+                    ),
+                    RemoteDesktopEvent::NotifyPointerMotion(notify_pointer_motion) => forward
+                        .empty(
+                            xdg_bypass,
+                            return_tx,
+                            "RemoteDesktopProxy.NotifyPointerMotion",
+                            async move {
+                                this_proxy
+                                    .notify_pointer_motion(
+                                        paths.session_handle(),
+                                        notify_pointer_motion.options,
+                                        notify_pointer_motion.dx,
+                                        notify_pointer_motion.dy,
+                                    )
+                                    .await
+                            },
+                        ),
+                    RemoteDesktopEvent::NotifyPointerMotionAbsolute(
+                        notify_pointer_motion_absolute,
+                    ) => forward.empty(
+                        xdg_bypass,
+                        return_tx,
+                        "RemoteDesktopProxy.NotifyPointerMotionAbsolute",
+                        async move {
+                            this_proxy
+                                .notify_pointer_motion_absolute(
+                                    paths.session_handle(),
+                                    notify_pointer_motion_absolute.options,
+                                    notify_pointer_motion_absolute.stream,
+                                    notify_pointer_motion_absolute.x,
+                                    notify_pointer_motion_absolute.y,
+                                )
+                                .await
+                        },
+                    ),
+                    RemoteDesktopEvent::NotifyPointerButton(notify_pointer_button) => forward
+                        .empty(
+                            xdg_bypass,
+                            return_tx,
+                            "RemoteDesktopProxy.NotifyPointerButton",
+                            async move {
+                                this_proxy
+                                    .notify_pointer_button(
+                                        paths.session_handle(),
+                                        notify_pointer_button.options,
+                                        notify_pointer_button.button,
+                                        notify_pointer_button.state,
+                                    )
+                                    .await
+                            },
+                        ),
+                    RemoteDesktopEvent::NotifyPointerAxis(notify_pointer_axis) => forward.empty(
+                        xdg_bypass,
+                        return_tx,
+                        "RemoteDesktopProxy.NotifyPointerAxis",
+                        async move {
+                            this_proxy
+                                .notify_pointer_axis(
+                                    paths.session_handle(),
+                                    notify_pointer_axis.options,
+                                    notify_pointer_axis.dx,
+                                    notify_pointer_axis.dy,
+                                )
+                                .await
+                        },
+                    ),
+                    RemoteDesktopEvent::NotifyPointerAxisDiscrete(notify_pointer_axis_discrete) => {
+                        forward.empty(
+                            xdg_bypass,
+                            return_tx,
+                            "RemoteDesktopProxy.NotifyPointerAxisDiscrete",
+                            async move {
+                                this_proxy
+                                    .notify_pointer_axis_discrete(
+                                        paths.session_handle(),
+                                        notify_pointer_axis_discrete.options,
+                                        notify_pointer_axis_discrete.axis,
+                                        notify_pointer_axis_discrete.steps,
+                                    )
+                                    .await
+                            },
+                        )
+                    }
+                    RemoteDesktopEvent::NotifyKeyboardKeycode(notify_keyboard_keycode) => forward
+                        .empty(
+                            xdg_bypass,
+                            return_tx,
+                            "RemoteDesktopProxy.NotifyKeyboardKeycode",
+                            async move {
+                                this_proxy
+                                    .notify_keyboard_keycode(
+                                        paths.session_handle(),
+                                        notify_keyboard_keycode.options,
+                                        notify_keyboard_keycode.keycode,
+                                        notify_keyboard_keycode.state,
+                                    )
+                                    .await
+                            },
+                        ),
+                    RemoteDesktopEvent::NotifyKeyboardKeysym(notify_keyboard_keysym) => forward
+                        .empty(
+                            xdg_bypass,
+                            return_tx,
+                            "RemoteDesktopProxy.NotifyKeyboardKeysym",
+                            async move {
+                                this_proxy
+                                    .notify_keyboard_keysym(
+                                        paths.session_handle(),
+                                        notify_keyboard_keysym.options,
+                                        notify_keyboard_keysym.keysym,
+                                        notify_keyboard_keysym.state,
+                                    )
+                                    .await
+                            },
+                        ),
+                    RemoteDesktopEvent::NotifyTouchDown(notify_touch_down) => forward.empty(
+                        xdg_bypass,
+                        return_tx,
+                        "RemoteDesktopProxy.NotifyTouchDown",
+                        async move {
+                            this_proxy
+                                .notify_touch_down(
+                                    paths.session_handle(),
+                                    notify_touch_down.options,
+                                    notify_touch_down.stream,
+                                    notify_touch_down.slot,
+                                    notify_touch_down.x,
+                                    notify_touch_down.y,
+                                )
+                                .await
+                        },
+                    ),
+                    RemoteDesktopEvent::NotifyTouchMotion(notify_touch_motion) => forward.empty(
+                        xdg_bypass,
+                        return_tx,
+                        "RemoteDesktopProxy.NotifyTouchMotion",
+                        async move {
+                            this_proxy
+                                .notify_touch_motion(
+                                    paths.session_handle(),
+                                    notify_touch_motion.options,
+                                    notify_touch_motion.stream,
+                                    notify_touch_motion.slot,
+                                    notify_touch_motion.x,
+                                    notify_touch_motion.y,
+                                )
+                                .await
+                        },
+                    ),
+                    RemoteDesktopEvent::NotifyTouchUp(notify_touch_up) => forward.empty(
+                        xdg_bypass,
+                        return_tx,
+                        "RemoteDesktopProxy.NotifyTouchUp",
+                        async move {
+                            this_proxy
+                                .notify_touch_up(
+                                    paths.session_handle(),
+                                    notify_touch_up.options,
+                                    notify_touch_up.slot,
+                                )
+                                .await
+                        },
+                    ),
+                    RemoteDesktopEvent::ConnectToEIS(connect_to_eis) => forward.fd(
+                        xdg_bypass,
+                        return_tx,
+                        "RemoteDesktopProxy.ConnectToEIS",
+                        async move {
+                            this_proxy
+                                .connect_to_eis(
+                                    paths.session_handle(),
+                                    connect_to_eis.app_id,
+                                    connect_to_eis.options,
+                                )
+                                .await
+                        },
+                    ),
+                    RemoteDesktopEvent::GetPropertiesAvilableDeviceTypes => forward.value(
+                        xdg_bypass,
+                        return_tx,
+                        "RemoteDesktopProxy.AvailableDeviceTypes",
+                        async move { this_proxy.available_device_types().await },
+                    ),
+                    RemoteDesktopEvent::GetPropertiesVersion => forward.value(
+                        xdg_bypass,
+                        return_tx,
+                        "RemoteDesktopProxy.Version",
+                        async move { this_proxy.version().await },
+                    ),
                 }
             }
             _ => Err(anyhow::anyhow!(
-                "[RemoteDesktopProxy] Wrong event type, should be RemoteDesktop"
+                "[RemoteDesktopProxy] Wrong event type, should be CreateSession, Close, CloseRequest, RemoteDesktop or ScreenCast"
             )),
         }
     }
 
+    fn uses_destination(&self, service_name: &str) -> bool {
+        self.proxy.inner().destination().as_str() == service_name
+    }
+
     fn new(
         xdg_bypass: &mut crate::event_handler::XdgBypass,
         session: zbus::zvariant::OwnedObjectPath,
@@ -249,22 +520,17 @@ impl EventHandler for RemoteDesktopProxy {
     where
         Self: Sized,
     {
-        match &xdg_bypass.config.remote_desktop_mode {
+        match xdg_bypass
+            .config
+            .mode(crate::event_handler::Interface::RemoteDesktop)
+        {
             crate::event_handler::WorkingMode::Proxy(destination) => {
-                let proxy = futures::executor::block_on(async {
-                    RemoteDesktopProxySenderTraitProxy::builder(
-                        &xdg_bypass.connection.clone().into(),
-                    )
-                    .destination(destination.service_name.clone())?
-                    .path(destination.object_path.clone())?
-                    .build()
-                    .await
-                })
-                .with_context(|| "[RemoteDesktopPoxy] Fail to connect to proxy destination.")?;
-                Ok(Box::new(Self {
-                    proxyed_session_handle: session,
-                    proxy,
-                }))
+                let destination = destination.clone();
+                Ok(Box::new(Self::with_destination(
+                    xdg_bypass,
+                    session,
+                    &destination,
+                )?))
             }
             crate::event_handler::WorkingMode::Server => Err(anyhow::anyhow!(
                 "[RemoteDesktopProxy] Wrong handler type, should be proxy"
@@ -272,3 +538,78 @@ impl EventHandler for RemoteDesktopProxy {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::event_handler::events::remote_desktop::Start;
+    use crate::event_handler::policy::PolicyRule;
+    use crate::event_handler::testing;
+    use crate::event_handler::{CreateSession, Event, EventResponse, Interface, XdgBypassConfig};
+
+    fn create_session() -> Event {
+        Event::CreateSession(CreateSession {
+            interface: Interface::RemoteDesktop,
+            handle: testing::handle("c"),
+            app_id: "org.example.App".to_string(),
+            options: HashMap::new(),
+        })
+    }
+
+    #[test]
+    fn refused_sessions_are_dropped() {
+        let mut event_loop = calloop::EventLoop::try_new().unwrap();
+        let (mut xdg_bypass, _peer, destination) =
+            testing::proxied(&event_loop, XdgBypassConfig::default());
+        let session = testing::session("t");
+
+        destination.refuse.store(true, Ordering::Relaxed);
+        let created =
+            testing::request(&mut event_loop, &mut xdg_bypass, &session, create_session());
+        assert!(matches!(created, Some(EventResponse::Standard(2, _))));
+        assert!(!xdg_bypass.sessions.contains_key(&session));
+        assert!(xdg_bypass.policies.rule(&session).is_none());
+
+        // The frontend may try again with the same handle.
+        destination.refuse.store(false, Ordering::Relaxed);
+        let created =
+            testing::request(&mut event_loop, &mut xdg_bypass, &session, create_session());
+        assert!(matches!(created, Some(EventResponse::Standard(0, _))));
+        assert!(xdg_bypass.sessions.contains_key(&session));
+    }
+
+    #[test]
+    fn max_duration_closes_the_destination_session() {
+        let mut config = XdgBypassConfig::default();
+        config.policy.push(PolicyRule {
+            max_duration: Some(Duration::from_millis(200)),
+            ..PolicyRule::default()
+        });
+        let mut event_loop = calloop::EventLoop::try_new().unwrap();
+        let (mut xdg_bypass, _peer, destination) = testing::proxied(&event_loop, config);
+
+        let session = testing::session("t");
+        let created =
+            testing::request(&mut event_loop, &mut xdg_bypass, &session, create_session());
+
+        assert!(matches!(created, Some(EventResponse::Standard(0, _))));
+        let started = testing::request(
+            &mut event_loop,
+            &mut xdg_bypass,
+            &session,
+            Event::RemoteDesktop(RemoteDesktopEvent::Start(Start {
+                handle: testing::handle("s"),
+                app_id: "org.example.App".to_string(),
+                parent_window: String::new(),
+                options: HashMap::new(),
+            })),
+        );
+        assert!(matches!(started, Some(EventResponse::Standard(0, _))));
+
+        testing::run_until(&mut event_loop, &mut xdg_bypass, |_| destination.closed());
+        assert!(!xdg_bypass.sessions.contains_key(&session));
+    }
+}
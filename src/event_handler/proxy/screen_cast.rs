@@ -4,7 +4,10 @@ use anyhow::Context;
 use zbus::zvariant::{ObjectPath, OwnedValue};
 use zbus::{proxy, zvariant};
 
-use crate::event_handler::EventHandler;
+use crate::event_handler::events::screen_cast::ScreenCastEvent;
+use crate::event_handler::proxy::destinations::destination_proxy;
+use crate::event_handler::proxy::{DownstreamPaths, Forward, close_downstream, watch_closed};
+use crate::event_handler::{EventHandler, ProxyOptions};
 
 #[proxy(interface = "org.freedesktop.impl.portal.ScreenCast")]
 trait ScreenCastProxySenderTrait {
@@ -49,10 +52,85 @@ trait ScreenCastProxySenderTrait {
     ) -> zbus::fdo::Result<zbus::zvariant::OwnedFd>;
 }
 
-#[allow(dead_code)]
+/// Version and available source types of the ScreenCast backend at `destination`,
+/// read without caching.
+pub async fn probe(
+    connection: &zbus::Connection,
+    destination: &crate::event_handler::ProxyDestination,
+) -> zbus::fdo::Result<(u32, u32)> {
+    let proxy = ScreenCastProxySenderTraitProxy::builder(connection)
+        .destination(destination.service_name())?
+        .path(destination.object_path())?
+        .cache_properties(zbus::proxy::CacheProperties::No)
+        .build()
+        .await?;
+    Ok((
+        proxy.version().await?,
+        proxy.available_source_types().await?,
+    ))
+}
+
 pub struct ScreenCastProxy {
     proxyed_session_handle: zvariant::OwnedObjectPath,
     proxy: ScreenCastProxySenderTraitProxy<'static>,
+    /// Paths of the session at the destination.
+    paths: DownstreamPaths,
+    /// Proxy options of the configuration the session was created with.
+    options: ProxyOptions,
+    /// Whether the session exists at the destination. A RemoteDesktop session
+    /// served locally only creates it there once sources are selected.
+    session_created: bool,
+    /// Watch of the downstream `Closed` signal, set when this handler created
+    /// the downstream session and so has to close it.
+    closed_watch: Option<futures::future::AbortHandle>,
+}
+
+impl ScreenCastProxy {
+    /// Proxy the ScreenCast calls of `session` to `destination`, where the
+    /// session is known at `paths`.
+    pub fn with_destination(
+        xdg_bypass: &mut crate::event_handler::XdgBypass,
+        session: zvariant::OwnedObjectPath,
+        destination: &crate::event_handler::ProxyDestination,
+        paths: DownstreamPaths,
+        session_created: bool,
+    ) -> anyhow::Result<Self> {
+        let proxy = destination_proxy(
+            xdg_bypass,
+            crate::event_handler::Interface::ScreenCast,
+            destination,
+        )
+        .with_context(|| "[ScreenCastProxy] Fail to connect to proxy destination.")?;
+        Ok(Self {
+            proxyed_session_handle: session,
+            proxy: proxy.into(),
+            paths,
+            options: xdg_bypass.config.proxy.clone(),
+            session_created,
+            closed_watch: None,
+        })
+    }
+
+    fn watch_closed(
+        &mut self,
+        xdg_bypass: &mut crate::event_handler::XdgBypass,
+    ) -> anyhow::Result<()> {
+        self.closed_watch = Some(watch_closed(
+            xdg_bypass,
+            self.proxy.inner(),
+            &self.paths,
+            self.proxyed_session_handle.clone(),
+        )?);
+        Ok(())
+    }
+}
+
+impl Drop for ScreenCastProxy {
+    fn drop(&mut self) {
+        if let Some(closed_watch) = self.closed_watch.take() {
+            closed_watch.abort();
+        }
+    }
 }
 
 impl EventHandler for ScreenCastProxy {
@@ -61,67 +139,142 @@ impl EventHandler for ScreenCastProxy {
         xdg_bypass: &mut crate::event_handler::XdgBypass,
         event: crate::event_handler::EventHandle,
     ) -> anyhow::Result<()> {
+        let this_proxy = self.proxy.clone();
+        let paths = self.paths.clone();
+        let forward = Forward::new(self.proxyed_session_handle.clone(), self.options.clone());
+        let return_tx = event.return_tx;
+
         match event.event {
-            crate::event_handler::Event::ScreenCast(screen_cast_event) => {
-                match screen_cast_event {
-                    crate::event_handler::events::screen_cast::ScreenCastEvent::SelectSources(select_sources) => {
-                        let this_proxy = self.proxy.clone();
-                        xdg_bypass.scheduler.schedule(async move {
-                            let response = this_proxy.select_sources(select_sources.handle, select_sources.session_handle, select_sources.app_id, select_sources.options).await.unwrap();
-                            let _ = event.return_tx.send(crate::event_handler::EventResponse::Standard(response.0, OwnedValue::from(response.1)));
-                        }).with_context(|| "")?;
-                        Ok(())
-                    },
-                    crate::event_handler::events::screen_cast::ScreenCastEvent::Start(start) => {
-                        let this_proxy = self.proxy.clone();
-                        xdg_bypass.scheduler.schedule(async move {
-                            let response = this_proxy.start(start.handle, start.session_handle, start.app_id, start.parent_window, start.options).await.unwrap();
-                            let _ = event.return_tx.send(crate::event_handler::EventResponse::Standard(response.0, OwnedValue::from(response.1)));
-                        }).with_context(|| "")?;
-                        Ok(())
-                    },
-                    crate::event_handler::events::screen_cast::ScreenCastEvent::OpenPipeWireRemote(open_pipe_wire_remote) => {
-                        let this_proxy = self.proxy.clone();
-                        xdg_bypass.scheduler.schedule(async move {
-                            // Note: open_pipe_wire_remote signature in dbus usually doesn't take app_id, but the event has it.
-                            // We ignore app_id here unless required. 
-                            let response = this_proxy.open_pipe_wire_remote(open_pipe_wire_remote.session_handle, open_pipe_wire_remote.options).await.unwrap();
-                            let _ = event.return_tx.send(crate::event_handler::EventResponse::Value(zvariant::Value::from(zvariant::Fd::from(response)).try_to_owned().unwrap()));
-                        }).with_context(|| "")?;
-                        Ok(())
-                    },
-                    crate::event_handler::events::screen_cast::ScreenCastEvent::GetPropertiesAvailableSourceTypes => {
-                        let this_proxy = self.proxy.clone();
-                        xdg_bypass.scheduler.schedule(async move {
-                            let response = this_proxy.available_source_types().await.unwrap();
-                            let _ = event.return_tx.send(crate::event_handler::EventResponse::Value(OwnedValue::from(response)));
-                        }).with_context(|| "")?;
-                        Ok(())
-                    },
-                    crate::event_handler::events::screen_cast::ScreenCastEvent::GetPropertiesAvailableCursorModes => {
-                        let this_proxy = self.proxy.clone();
-                        xdg_bypass.scheduler.schedule(async move {
-                            let response = this_proxy.available_cursor_modes().await.unwrap();
-                            let _ = event.return_tx.send(crate::event_handler::EventResponse::Value(OwnedValue::from(response)));
-                        }).with_context(|| "")?;
-                        Ok(())
-                    },
-                    crate::event_handler::events::screen_cast::ScreenCastEvent::GetPropertiesVersion => {
-                        let this_proxy = self.proxy.clone();
-                        xdg_bypass.scheduler.schedule(async move {
-                            let response = this_proxy.version().await.unwrap();
-                            let _ = event.return_tx.send(crate::event_handler::EventResponse::Value(OwnedValue::from(response)));
-                        }).with_context(|| "")?;
-                        Ok(())
+            crate::event_handler::Event::CreateSession(create_session) => {
+                self.session_created = true;
+                self.watch_closed(xdg_bypass)?;
+                forward.standard(
+                    xdg_bypass,
+                    return_tx,
+                    "ScreenCastProxy.CreateSession",
+                    async move {
+                        this_proxy
+                            .create_session(
+                                paths.request_handle(&create_session.handle),
+                                paths.session_handle(),
+                                create_session.app_id,
+                                create_session.options,
+                            )
+                            .await
                     },
+                )
+            }
+            crate::event_handler::Event::Close => {
+                if let Some(closed_watch) = self.closed_watch.take() {
+                    closed_watch.abort();
+                    close_downstream(xdg_bypass, self.proxy.inner(), &self.paths)?;
                 }
+                let _ = return_tx.send(crate::event_handler::EventResponse::Standard(
+                    0,
+                    crate::event_handler::empty_results(),
+                ));
+                Ok(())
             }
+            crate::event_handler::Event::CloseRequest(close_request) => forward.close_request(
+                xdg_bypass,
+                return_tx,
+                "ScreenCastProxy.CloseRequest",
+                this_proxy.inner(),
+                paths.request_handle(&close_request.handle),
+            ),
+            crate::event_handler::Event::ScreenCast(screen_cast_event) => match screen_cast_event {
+                ScreenCastEvent::SelectSources(select_sources) => {
+                    let create_session = !self.session_created;
+                    if create_session {
+                        self.session_created = true;
+                        self.watch_closed(xdg_bypass)?;
+                    }
+                    forward.standard(
+                        xdg_bypass,
+                        return_tx,
+                        "ScreenCastProxy.SelectSources",
+                        async move {
+                            if create_session {
+                                // The frontend's handle stays with `SelectSources`, so
+                                // closing its request reaches the call it waits for.
+                                let created = this_proxy
+                                    .create_session(
+                                        paths.own_request_handle(),
+                                        paths.session_handle(),
+                                        select_sources.app_id.clone(),
+                                        HashMap::new(),
+                                    )
+                                    .await;
+                                if !matches!(created, Ok((0, _))) {
+                                    return created;
+                                }
+                            }
+                            this_proxy
+                                .select_sources(
+                                    paths.request_handle(&select_sources.handle),
+                                    paths.session_handle(),
+                                    select_sources.app_id,
+                                    select_sources.options,
+                                )
+                                .await
+                        },
+                    )
+                }
+                ScreenCastEvent::Start(start) => {
+                    forward.start(xdg_bypass, return_tx, "ScreenCastProxy.Start", async move {
+                        this_proxy
+                            .start(
+                                paths.request_handle(&start.handle),
+                                paths.session_handle(),
+                                start.app_id,
+                                start.parent_window,
+                                start.options,
+                            )
+                            .await
+                    })
+                }
+                ScreenCastEvent::OpenPipeWireRemote(open_pipe_wire_remote) => forward.fd(
+                    xdg_bypass,
+                    return_tx,
+                    "ScreenCastProxy.OpenPipeWireRemote",
+                    async move {
+                        this_proxy
+                            .open_pipe_wire_remote(
+                                paths.session_handle(),
+                                open_pipe_wire_remote.options,
+                            )
+                            .await
+                    },
+                ),
+                ScreenCastEvent::GetPropertiesAvailableSourceTypes => forward.value(
+                    xdg_bypass,
+                    return_tx,
+                    "ScreenCastProxy.AvailableSourceTypes",
+                    async move { this_proxy.available_source_types().await },
+                ),
+                ScreenCastEvent::GetPropertiesAvailableCursorModes => forward.value(
+                    xdg_bypass,
+                    return_tx,
+                    "ScreenCastProxy.AvailableCursorModes",
+                    async move { this_proxy.available_cursor_modes().await },
+                ),
+                ScreenCastEvent::GetPropertiesVersion => forward.value(
+                    xdg_bypass,
+                    return_tx,
+                    "ScreenCastProxy.Version",
+                    async move { this_proxy.version().await },
+                ),
+            },
             _ => Err(anyhow::anyhow!(
-                "[ScreenCastProxy] Wrong event type, should be ScreenCast"
+                "[ScreenCastProxy] Wrong event type, should be CreateSession, Close, CloseRequest or ScreenCast"
             )),
         }
     }
 
+    fn uses_destination(&self, service_name: &str) -> bool {
+        self.proxy.inner().destination().as_str() == service_name
+    }
+
     fn new(
         xdg_bypass: &mut crate::event_handler::XdgBypass,
         session: zbus::zvariant::OwnedObjectPath,
@@ -129,20 +282,19 @@ impl EventHandler for ScreenCastProxy {
     where
         Self: Sized,
     {
-        match &xdg_bypass.config.remote_desktop_mode {
+        match xdg_bypass
+            .config
+            .mode(crate::event_handler::Interface::ScreenCast)
+        {
             crate::event_handler::WorkingMode::Proxy(destination) => {
-                let proxy = futures::executor::block_on(async {
-                    ScreenCastProxySenderTraitProxy::builder(&xdg_bypass.connection.clone().into())
-                        .destination(destination.service_name.clone())?
-                        .path(destination.object_path.clone())?
-                        .build()
-                        .await
-                })
-                .with_context(|| "[ScreenCastProxy] Fail to connect to proxy destination.")?;
-                Ok(Box::new(Self {
-                    proxyed_session_handle: session,
-                    proxy,
-                }))
+                let destination = destination.clone();
+                Ok(Box::new(Self::with_destination(
+                    xdg_bypass,
+                    session,
+                    &destination,
+                    DownstreamPaths::new(),
+                    false,
+                )?))
             }
             crate::event_handler::WorkingMode::Server => Err(anyhow::anyhow!(
                 "[ScreenCastProxy] Wrong handler type, should be proxy"
@@ -173,13 +325,10 @@ mod tests {
         let available_cursor_modes = proxy.available_cursor_modes().await.unwrap();
         println!("Available cursor modes: {}", available_cursor_modes);
 
-        let handle = ObjectPath::try_from("/org/freedesktop/portal/desktop/request/123/456")
-            .unwrap()
-            .into();
+        let handle =
+            ObjectPath::try_from("/org/freedesktop/portal/desktop/request/123/456").unwrap();
         let session_handle =
-            ObjectPath::try_from("/org/freedesktop/portal/desktop/session/123/456")
-                .unwrap()
-                .into();
+            ObjectPath::try_from("/org/freedesktop/portal/desktop/session/123/456").unwrap();
         let app_id = "org.example.Test".to_string();
         let options = HashMap::new();
 
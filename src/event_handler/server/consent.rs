@@ -0,0 +1,347 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
+use std::pin::{Pin, pin};
+use std::sync::OnceLock;
+
+use async_io::Timer;
+use futures::StreamExt;
+use futures::channel::{mpsc, oneshot};
+use futures::future::{Either, select};
+use futures::lock::Mutex;
+use tracing::{info, warn};
+use zbus::proxy;
+use zbus::zvariant::Value;
+
+use crate::event_handler::{ConsentMethod, ConsentOptions};
+
+#[proxy(
+    interface = "org.freedesktop.Notifications",
+    default_service = "org.freedesktop.Notifications",
+    default_path = "/org/freedesktop/Notifications"
+)]
+trait NotificationsProxySenderTrait {
+    #[allow(clippy::too_many_arguments)]
+    fn notify(
+        &self,
+        app_name: &str,
+        replaces_id: u32,
+        app_icon: &str,
+        summary: &str,
+        body: &str,
+        actions: &[&str],
+        hints: HashMap<&str, Value<'_>>,
+        expire_timeout: i32,
+    ) -> zbus::fdo::Result<u32>;
+
+    fn close_notification(&self, id: u32) -> zbus::fdo::Result<()>;
+
+    #[zbus(signal)]
+    fn action_invoked(&self, id: u32, action_key: String) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    fn notification_closed(&self, id: u32, reason: u32) -> zbus::Result<()>;
+}
+
+/// Lines typed on the terminal, read by one thread for every prompt. The lock
+/// serializes terminal prompts, so an answer never goes to the wrong one.
+static TERMINAL: OnceLock<Mutex<mpsc::UnboundedReceiver<String>>> = OnceLock::new();
+
+/// How a pending `Start` was decided.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Answer {
+    /// Denied by the user, or the prompt went unanswered.
+    Denied,
+    /// Approved by the user just now.
+    Approved,
+    /// Allowed by an earlier grant of the selected device types.
+    Granted,
+}
+
+/// What the user is asked to approve.
+#[derive(Debug, Clone)]
+pub struct Prompt {
+    pub app_id: String,
+    /// Name of the app from its desktop entry, see [`display_name`].
+    pub app_name: String,
+    /// Bitmask of the requested device types.
+    pub device_types: u32,
+}
+
+impl Prompt {
+    pub fn new(app_id: &str, device_types: u32) -> Self {
+        Self {
+            app_id: app_id.to_string(),
+            app_name: display_name(app_id),
+            device_types,
+        }
+    }
+
+    fn summary(&self) -> &'static str {
+        "Allow remote control?"
+    }
+
+    fn body(&self) -> String {
+        // Without a desktop entry the name is the app id already.
+        let app = match self.app_name == self.app_id || self.app_id.is_empty() {
+            true => self.app_name.clone(),
+            false => format!("{} ({})", self.app_name, self.app_id),
+        };
+        format!(
+            "{} wants to control this computer through a virtual {}.",
+            app,
+            device_names(self.device_types)
+        )
+    }
+}
+
+/// Ask the user to approve `prompt`, with `options.fallback` when the
+/// configured prompt can't be shown.
+///
+/// Resolves to `false` when the user denies, doesn't answer in time, or
+/// `cancel` fires or is dropped first.
+pub async fn ask(
+    connection: zbus::Connection,
+    prompt: Prompt,
+    options: ConsentOptions,
+    cancel: oneshot::Receiver<()>,
+) -> bool {
+    let app_name = prompt.app_name.clone();
+    let stop = async move {
+        let timed_out = match options.timeout {
+            Some(timeout) => matches!(
+                select(cancel, Timer::after(timeout)).await,
+                Either::Right(_)
+            ),
+            None => {
+                let _ = cancel.await;
+                false
+            }
+        };
+        match timed_out {
+            true => info!("[Consent] Prompt for {} timed out", app_name),
+            false => info!("[Consent] Prompt for {} withdrawn", app_name),
+        }
+    };
+    let mut stop = pin!(stop);
+
+    for method in [options.prompt, options.fallback] {
+        match ask_with(method, &connection, &prompt, stop.as_mut()).await {
+            Ok(allowed) => return allowed,
+            Err(e) => warn!("[Consent] {:?} prompt unavailable: {:#}", method, e),
+        }
+    }
+    false
+}
+
+async fn ask_with(
+    method: ConsentMethod,
+    connection: &zbus::Connection,
+    prompt: &Prompt,
+    stop: Pin<&mut impl Future<Output = ()>>,
+) -> anyhow::Result<bool> {
+    match method {
+        ConsentMethod::Notification => ask_notification(connection, prompt, stop).await,
+        ConsentMethod::Terminal => ask_terminal(prompt, stop).await,
+        ConsentMethod::Approve => Ok(true),
+        ConsentMethod::Deny => Ok(false),
+    }
+}
+
+async fn ask_notification(
+    connection: &zbus::Connection,
+    prompt: &Prompt,
+    stop: Pin<&mut impl Future<Output = ()>>,
+) -> anyhow::Result<bool> {
+    let notifications = NotificationsProxySenderTraitProxy::new(connection).await?;
+    // Subscribed before the notification exists, so no answer is missed.
+    let actions = notifications.receive_action_invoked().await?;
+    let closed = notifications.receive_notification_closed().await?;
+
+    let hints = HashMap::from([("urgency", Value::U8(2)), ("resident", Value::Bool(true))]);
+    let id = notifications
+        .notify(
+            "xdg-desktop-portal-bypass",
+            0,
+            "preferences-desktop-remote-desktop",
+            prompt.summary(),
+            &prompt.body(),
+            &["allow", "Allow", "deny", "Deny"],
+            hints,
+            0,
+        )
+        .await?;
+
+    let actions = actions.filter_map(|signal| async move {
+        let args = signal.args().ok()?;
+        (args.id == id).then(|| args.action_key == "allow")
+    });
+    // Dismissing the notification denies.
+    let closed = closed.filter_map(|signal| async move {
+        let args = signal.args().ok()?;
+        (args.id == id).then_some(false)
+    });
+    let mut answers = pin!(futures::stream::select(actions, closed));
+
+    match select(answers.next(), stop).await {
+        Either::Left((answer, _)) => Ok(answer.unwrap_or(false)),
+        Either::Right(_) => {
+            let _ = notifications.close_notification(id).await;
+            Ok(false)
+        }
+    }
+}
+
+async fn ask_terminal(
+    prompt: &Prompt,
+    stop: Pin<&mut impl Future<Output = ()>>,
+) -> anyhow::Result<bool> {
+    if !io::stdin().is_terminal() {
+        anyhow::bail!("stdin is not a terminal");
+    }
+
+    let question = format!("{} {} [y/N] ", prompt.summary(), prompt.body());
+    let asked = Cell::new(false);
+    let answer = async {
+        let mut lines = terminal_lines().lock().await;
+        // Lines typed while no prompt was shown answer nothing.
+        while let Ok(Some(_)) = lines.try_next() {}
+        eprint!("{}", question);
+        asked.set(true);
+        lines.next().await
+    };
+
+    match select(pin!(answer), stop).await {
+        Either::Left((Some(line), _)) => Ok(matches!(line.trim(), "y" | "Y" | "yes")),
+        Either::Left((None, _)) => anyhow::bail!("stdin was closed"),
+        Either::Right(_) => {
+            if asked.get() {
+                eprintln!();
+                eprintln!("The request was withdrawn.");
+            }
+            Ok(false)
+        }
+    }
+}
+
+/// Lines of stdin, read on a thread of their own since reading blocks.
+fn terminal_lines() -> &'static Mutex<mpsc::UnboundedReceiver<String>> {
+    TERMINAL.get_or_init(|| {
+        let (sender, receiver) = mpsc::unbounded();
+        std::thread::spawn(move || {
+            for line in io::stdin().lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if sender.unbounded_send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Mutex::new(receiver)
+    })
+}
+
+/// Human readable list of the device types in `device_types`.
+pub fn device_names(device_types: u32) -> String {
+    let names: Vec<&str> = [(1, "keyboard"), (2, "pointer"), (4, "touchscreen")]
+        .into_iter()
+        .filter(|(bit, _)| device_types & bit != 0)
+        .map(|(_, name)| name)
+        .collect();
+    match names.as_slice() {
+        [] => "input device".to_string(),
+        [name] => name.to_string(),
+        [rest @ .., last] => format!("{} and {}", rest.join(", "), last),
+    }
+}
+
+/// `Name` of the app's desktop entry, or the app id itself when there is none.
+pub fn display_name(app_id: &str) -> String {
+    if app_id.is_empty() {
+        return "An application".to_string();
+    }
+    data_dirs()
+        .into_iter()
+        .map(|dir| dir.join("applications").join(format!("{}.desktop", app_id)))
+        .find_map(|path| {
+            fs::read_to_string(path)
+                .ok()
+                .and_then(|content| desktop_entry_name(&content))
+        })
+        .unwrap_or_else(|| app_id.to_string())
+}
+
+/// Data directories desktop entries are installed in, most specific first.
+fn data_dirs() -> Vec<PathBuf> {
+    let data_home = env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")));
+    let data_dirs = env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|dirs| !dirs.is_empty())
+        .unwrap_or_else(|| "/usr/local/share:/usr/share".to_string());
+
+    data_home
+        .into_iter()
+        .chain(
+            data_dirs
+                .split(':')
+                .map(PathBuf::from)
+                .filter(|path| path.is_absolute()),
+        )
+        .collect()
+}
+
+/// Untranslated `Name` of the `[Desktop Entry]` group.
+fn desktop_entry_name(content: &str) -> Option<String> {
+    let mut in_entry = false;
+    for line in content.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_entry = line == "[Desktop Entry]";
+            continue;
+        }
+        if in_entry
+            && let Some((key, value)) = line.split_once('=')
+            && key.trim() == "Name"
+        {
+            return Some(value.trim().to_string());
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn desktop_entry_name_is_read() {
+        let content = "\
+[Desktop Action new]
+Name=New Window
+
+[Desktop Entry]
+Name[de]=Fernwartung
+Name = Remote Helper
+Exec=remote-helper
+";
+        assert_eq!(
+            desktop_entry_name(content).as_deref(),
+            Some("Remote Helper")
+        );
+        assert_eq!(desktop_entry_name("[Desktop Entry]\nExec=true"), None);
+    }
+
+    #[test]
+    fn device_types_are_named() {
+        assert_eq!(device_names(1), "keyboard");
+        assert_eq!(device_names(3), "keyboard and pointer");
+        assert_eq!(device_names(7), "keyboard, pointer and touchscreen");
+        assert_eq!(device_names(0), "input device");
+    }
+}
@@ -0,0 +1,466 @@
+use std::collections::HashMap;
+use std::os::unix::net::UnixStream;
+
+use anyhow::Context;
+use calloop::LoopHandle;
+use calloop::PostAction;
+use calloop::RegistrationToken;
+use futures::channel::oneshot;
+use reis::calloop::{EisRequestSource, EisRequestSourceEvent};
+use reis::eis;
+use reis::eis::connection::DisconnectReason;
+use reis::enumflags2::BitFlags;
+use reis::request::{self, DeviceCapability, EisRequest};
+use tracing::{debug, error};
+use zbus::zvariant;
+use zbus::zvariant::OwnedObjectPath;
+
+use crate::event_handler::Event;
+use crate::event_handler::EventHandle;
+use crate::event_handler::EventResponse;
+use crate::event_handler::XdgBypass;
+use crate::event_handler::events::Payload;
+use crate::event_handler::events::remote_desktop::{
+    NotifyKeyboardKeycode, NotifyPointerAxis, NotifyPointerAxisDiscrete, NotifyPointerButton,
+    NotifyPointerMotion, NotifyPointerMotionAbsolute, NotifyTouchDown, NotifyTouchMotion,
+    NotifyTouchUp, RemoteDesktopEvent,
+};
+use crate::event_handler::server::geometry::Stream;
+use crate::event_handler::server::touch::MAX_SLOTS;
+
+/// Scroll steps are sent in fractions of 120 per detent.
+const DISCRETE_STEP: i32 = 120;
+
+/// Serve EIS for `session` on one end of a socket pair and return the other end.
+///
+/// Requests of the EI client are turned into the `Notify*` events of the session,
+/// so they reach the same uinput devices as the D-Bus methods.
+pub fn connect(
+    loop_handle: &LoopHandle<'static, XdgBypass>,
+    session: OwnedObjectPath,
+    device_types: u32,
+    streams: Vec<Stream>,
+) -> anyhow::Result<(zvariant::OwnedFd, RegistrationToken)> {
+    let (server, client) = UnixStream::pair().with_context(|| "Failed to create EIS socket")?;
+    let context = eis::Context::new(server).with_context(|| "Failed to create EIS context")?;
+
+    let mut eis_session = EisSession {
+        session,
+        device_types,
+        streams,
+        seat: None,
+        devices: Vec::new(),
+        touch_slots: HashMap::new(),
+        scroll_remainder: [0; 2],
+    };
+    let token = loop_handle
+        .insert_source(
+            EisRequestSource::new(context, 1),
+            move |event, connection, xdg_bypass| {
+                Ok(match event {
+                    Ok(event) => eis_session.handle(xdg_bypass, connection, event),
+                    Err(e) => {
+                        error!(
+                            "[EIS] Session {} lost its EI client: {}",
+                            eis_session.session, e
+                        );
+                        PostAction::Remove
+                    }
+                })
+            },
+        )
+        .map_err(|e| e.error)
+        .with_context(|| "Failed to listen on EIS socket")?;
+
+    Ok((std::os::fd::OwnedFd::from(client).into(), token))
+}
+
+/// State of the EI client connected to one session.
+struct EisSession {
+    session: OwnedObjectPath,
+    device_types: u32,
+    streams: Vec<Stream>,
+    seat: Option<request::Seat>,
+    /// Kept so the devices stay alive as long as the client.
+    devices: Vec<request::Device>,
+    /// Slot of the touchscreen used by every EI touch id currently down.
+    touch_slots: HashMap<u32, u32>,
+    /// Discrete scroll of each axis not yet making up a whole step, vertical
+    /// first.
+    scroll_remainder: [i32; 2],
+}
+
+impl EisSession {
+    fn handle(
+        &mut self,
+        xdg_bypass: &mut XdgBypass,
+        connection: &request::Connection,
+        event: EisRequestSourceEvent,
+    ) -> PostAction {
+        // The session was closed under the client, stop serving it.
+        if !xdg_bypass.sessions.contains_key(&self.session) {
+            debug!("[EIS] Session {} is gone, disconnecting", self.session);
+            connection.disconnected(DisconnectReason::Disconnected, None);
+            let _ = connection.flush();
+            return PostAction::Remove;
+        }
+
+        match event {
+            EisRequestSourceEvent::Connected => {
+                if connection.context_type() != eis::handshake::ContextType::Sender {
+                    error!("[EIS] Session {} only accepts sender clients", self.session);
+                    connection.disconnected(
+                        DisconnectReason::Mode,
+                        Some("Only sender clients are supported"),
+                    );
+                    let _ = connection.flush();
+                    return PostAction::Remove;
+                }
+                self.seat = Some(connection.add_seat(Some("default"), self.capabilities()));
+            }
+            EisRequestSourceEvent::Request(EisRequest::Disconnect) => {
+                debug!("[EIS] Client of session {} disconnected", self.session);
+                return PostAction::Remove;
+            }
+            EisRequestSourceEvent::Request(EisRequest::Bind(bind)) => {
+                self.bind(bind.capabilities);
+            }
+            EisRequestSourceEvent::Request(EisRequest::ScrollDiscrete(scroll)) => {
+                let events = discrete_scroll(
+                    &mut self.scroll_remainder,
+                    scroll.discrete_dx,
+                    scroll.discrete_dy,
+                );
+                for event in events {
+                    self.dispatch(xdg_bypass, event);
+                }
+            }
+            EisRequestSourceEvent::Request(request) => {
+                if let Some(event) = self.translate(request) {
+                    self.dispatch(xdg_bypass, event);
+                }
+            }
+        }
+
+        let _ = connection.flush();
+        PostAction::Continue
+    }
+
+    /// Capabilities matching the device types selected for the session.
+    fn capabilities(&self) -> BitFlags<DeviceCapability> {
+        let mut capabilities = BitFlags::empty();
+        if self.device_types & 1 != 0 {
+            capabilities |= DeviceCapability::Keyboard;
+        }
+        if self.device_types & 2 != 0 {
+            capabilities |= DeviceCapability::Pointer
+                | DeviceCapability::PointerAbsolute
+                | DeviceCapability::Button
+                | DeviceCapability::Scroll;
+        }
+        if self.device_types & 4 != 0 {
+            capabilities |= DeviceCapability::Touch;
+        }
+        capabilities
+    }
+
+    /// Add one device for every capability group the client bound to.
+    fn bind(&mut self, capabilities: BitFlags<DeviceCapability>) {
+        let Some(seat) = &self.seat else {
+            return;
+        };
+        let capabilities = capabilities & self.capabilities();
+        let pointer_extras = DeviceCapability::Button | DeviceCapability::Scroll;
+
+        let groups = [
+            (
+                "keyboard",
+                BitFlags::from(DeviceCapability::Keyboard),
+                false,
+            ),
+            ("pointer", pointer_extras | DeviceCapability::Pointer, false),
+            (
+                "absolute pointer",
+                pointer_extras | DeviceCapability::PointerAbsolute,
+                true,
+            ),
+            ("touchscreen", BitFlags::from(DeviceCapability::Touch), true),
+        ];
+
+        for (name, group, absolute) in groups {
+            // Buttons and scroll alone do not make a device.
+            if !capabilities.intersects(group & !pointer_extras) {
+                continue;
+            }
+            let device = seat.add_device(
+                Some(name),
+                eis::device::DeviceType::Virtual,
+                group,
+                |device| {
+                    // Absolute positions are in the desktop space the streams are laid out in.
+                    if absolute {
+                        for Stream { rect: stream, .. } in &self.streams {
+                            device.device().region(
+                                stream.x.max(0) as u32,
+                                stream.y.max(0) as u32,
+                                stream.width as u32,
+                                stream.height as u32,
+                                1.0,
+                            );
+                        }
+                    }
+                },
+            );
+            device.resumed();
+            self.devices.push(device);
+        }
+    }
+
+    /// The `Notify*` event equivalent to an EI request, if any.
+    fn translate(&mut self, request: EisRequest) -> Option<RemoteDesktopEvent> {
+        let options = HashMap::new();
+
+        match request {
+            EisRequest::PointerMotion(motion) => Some(RemoteDesktopEvent::NotifyPointerMotion(
+                NotifyPointerMotion {
+                    options,
+                    dx: motion.dx as f64,
+                    dy: motion.dy as f64,
+                },
+            )),
+            EisRequest::PointerMotionAbsolute(motion) => {
+                let (stream, x, y) =
+                    self.stream_position(motion.dx_absolute, motion.dy_absolute)?;
+                Some(RemoteDesktopEvent::NotifyPointerMotionAbsolute(
+                    NotifyPointerMotionAbsolute {
+                        options,
+                        stream,
+                        x,
+                        y,
+                    },
+                ))
+            }
+            EisRequest::Button(button) => {
+                // EI buttons are already evdev codes.
+                Some(RemoteDesktopEvent::NotifyPointerButton(
+                    NotifyPointerButton {
+                        options,
+                        button: button.button as i32,
+                        state: button.state.into(),
+                    },
+                ))
+            }
+            EisRequest::ScrollDelta(scroll) => {
+                // EI deltas are in logical pixels like the ones of
+                // `NotifyPointerAxis`, the session converts both the same way.
+                Some(RemoteDesktopEvent::NotifyPointerAxis(NotifyPointerAxis {
+                    options,
+                    dx: scroll.dx as f64,
+                    dy: scroll.dy as f64,
+                }))
+            }
+            EisRequest::KeyboardKey(key) => {
+                // No keymap is sent to the client, so keys are evdev codes.
+                Some(RemoteDesktopEvent::NotifyKeyboardKeycode(
+                    NotifyKeyboardKeycode {
+                        options,
+                        keycode: key.key as i32,
+                        state: key.state.into(),
+                    },
+                ))
+            }
+            EisRequest::TouchDown(touch) => {
+                let (stream, x, y) = self.stream_position(touch.x, touch.y)?;
+                let slot =
+                    (0..MAX_SLOTS).find(|slot| !self.touch_slots.values().any(|s| s == slot));
+                let Some(slot) = slot else {
+                    error!("[EIS] Session {} has no touch slot left", self.session);
+                    return None;
+                };
+                self.touch_slots.insert(touch.touch_id, slot);
+                Some(RemoteDesktopEvent::NotifyTouchDown(NotifyTouchDown {
+                    options,
+                    stream,
+                    slot,
+                    x,
+                    y,
+                }))
+            }
+            EisRequest::TouchMotion(touch) => {
+                let slot = *self.touch_slots.get(&touch.touch_id)?;
+                let (stream, x, y) = self.stream_position(touch.x, touch.y)?;
+                Some(RemoteDesktopEvent::NotifyTouchMotion(NotifyTouchMotion {
+                    options,
+                    stream,
+                    slot,
+                    x,
+                    y,
+                }))
+            }
+            EisRequest::TouchUp(request::TouchUp { touch_id, .. })
+            | EisRequest::TouchCancel(request::TouchCancel { touch_id, .. }) => {
+                let slot = self.touch_slots.remove(&touch_id)?;
+                Some(RemoteDesktopEvent::NotifyTouchUp(NotifyTouchUp {
+                    options,
+                    slot,
+                }))
+            }
+            // Every event is emitted on its own, frames and emulation state
+            // carry nothing the uinput devices need.
+            _ => None,
+        }
+    }
+
+    /// Stream containing the desktop position `x`, `y` and the position relative to it.
+    fn stream_position(&self, x: f32, y: f32) -> Option<(u32, f64, f64)> {
+        let (x, y) = (x as f64, y as f64);
+        let found = self.streams.iter().find(|Stream { rect: stream, .. }| {
+            x >= stream.x as f64
+                && y >= stream.y as f64
+                && x < (stream.x + stream.width) as f64
+                && y < (stream.y + stream.height) as f64
+        });
+
+        match found {
+            Some(Stream { id, rect: stream }) => {
+                Some((*id, x - stream.x as f64, y - stream.y as f64))
+            }
+            None => {
+                debug!(
+                    "[EIS] Position {:?},{:?} is outside every stream",
+                    Payload(&x),
+                    Payload(&y)
+                );
+                None
+            }
+        }
+    }
+
+    /// Hand the event to the session the way D-Bus calls are, so the policy
+    /// and the audit log see EI input as well.
+    fn dispatch(&self, xdg_bypass: &mut XdgBypass, event: RemoteDesktopEvent) {
+        let (return_tx, mut return_rx) = oneshot::channel();
+        xdg_bypass.handle(EventHandle {
+            session: self.session.clone(),
+            event: Event::RemoteDesktop(event),
+            return_tx,
+        });
+
+        // Notify events are only answered when they fail.
+        if let Ok(Some(EventResponse::Standard(response, _))) = return_rx.try_recv()
+            && response != 0
+        {
+            debug!(
+                "[EIS] Session {} failed to handle an EI request with {}",
+                self.session, response
+            );
+        }
+    }
+}
+
+/// A `NotifyPointerAxisDiscrete` for each axis an EI discrete scroll moves by
+/// whole steps, axis 0 being vertical and 1 horizontal.
+///
+/// Fractions of a step add up in `remainder` until they make one, scrolling the
+/// other way starts over.
+fn discrete_scroll(
+    remainder: &mut [i32; 2],
+    discrete_dx: i32,
+    discrete_dy: i32,
+) -> Vec<RemoteDesktopEvent> {
+    [(0, discrete_dy), (1, discrete_dx)]
+        .into_iter()
+        .filter_map(|(axis, discrete)| {
+            let pending = &mut remainder[axis as usize];
+            if pending.signum() == -discrete.signum() {
+                *pending = 0;
+            }
+            *pending += discrete;
+            let steps = *pending / DISCRETE_STEP;
+            *pending %= DISCRETE_STEP;
+            (steps != 0).then(|| {
+                RemoteDesktopEvent::NotifyPointerAxisDiscrete(NotifyPointerAxisDiscrete {
+                    options: HashMap::new(),
+                    axis,
+                    steps,
+                })
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_handler::server::geometry::Rect;
+
+    fn eis_session() -> EisSession {
+        EisSession {
+            session: OwnedObjectPath::try_from("/org/freedesktop/portal/desktop/session/1/t")
+                .unwrap(),
+            device_types: 7,
+            streams: vec![
+                Stream {
+                    id: 0,
+                    rect: Rect {
+                        x: 0,
+                        y: 0,
+                        width: 1920,
+                        height: 1080,
+                    },
+                },
+                Stream {
+                    id: 1,
+                    rect: Rect {
+                        x: 1920,
+                        y: 0,
+                        width: 2560,
+                        height: 1440,
+                    },
+                },
+            ],
+            seat: None,
+            devices: Vec::new(),
+            touch_slots: HashMap::new(),
+            scroll_remainder: [0; 2],
+        }
+    }
+
+    #[test]
+    fn discrete_scroll_moves_every_axis() {
+        let mut remainder = [0; 2];
+        let mut axes = |dx, dy| {
+            discrete_scroll(&mut remainder, dx, dy)
+                .into_iter()
+                .map(|event| match event {
+                    RemoteDesktopEvent::NotifyPointerAxisDiscrete(scroll) => {
+                        (scroll.axis, scroll.steps)
+                    }
+                    event => panic!("unexpected {:?}", event),
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(axes(240, -120), vec![(0, -1), (1, 2)]);
+        assert_eq!(axes(0, 120), vec![(0, 1)]);
+        assert_eq!(axes(60, 0), vec![]);
+        assert_eq!(axes(90, 30), vec![(1, 1)]);
+        assert_eq!(axes(0, 90), vec![(0, 1)]);
+        // Scrolling back drops what was left of the other direction.
+        assert_eq!(axes(-60, -90), vec![]);
+        assert_eq!(axes(-60, 0), vec![(1, -1)]);
+    }
+
+    #[test]
+    fn positions_are_relative_to_their_stream() {
+        let session = eis_session();
+        assert_eq!(
+            session.stream_position(100.0, 200.0),
+            Some((0, 100.0, 200.0))
+        );
+        assert_eq!(
+            session.stream_position(2020.0, 1200.0),
+            Some((1, 100.0, 1200.0))
+        );
+        assert_eq!(session.stream_position(100.0, 1200.0), None);
+    }
+}
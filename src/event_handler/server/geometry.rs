@@ -0,0 +1,289 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use tracing::debug;
+use zbus::zvariant::OwnedValue;
+
+/// Size used when no connected output can be found.
+const FALLBACK_OUTPUT: Rect = Rect {
+    x: 0,
+    y: 0,
+    width: 1920,
+    height: 1080,
+};
+
+/// A rectangle in the compositor's logical coordinate space, or in physical
+/// pixels when it comes from [`drm_outputs`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Rect {
+    /// Smallest rectangle containing both `self` and `other`.
+    pub fn union(self, other: Rect) -> Rect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        let right = (self.x + self.width).max(other.x + other.width);
+        let bottom = (self.y + self.height).max(other.y + other.height);
+
+        Rect {
+            x,
+            y,
+            width: right - x,
+            height: bottom - y,
+        }
+    }
+}
+
+/// A stream absolute positions can refer to. `id` is the value clients pass as
+/// `stream`, the PipeWire node id for ScreenCast streams.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stream {
+    pub id: u32,
+    pub rect: Rect,
+}
+
+/// Streams listed in the results of a ScreenCast `Start`, as `a(ua{sv})`.
+///
+/// Streams without a positive size can't be mapped and are skipped, a missing
+/// position means the stream is at the origin.
+pub fn screen_cast_streams(results: &HashMap<String, OwnedValue>) -> Vec<Stream> {
+    let Some(streams) = results
+        .get("streams")
+        .and_then(|streams| streams.try_clone().ok())
+        .and_then(|streams| Vec::<(u32, HashMap<String, OwnedValue>)>::try_from(streams).ok())
+    else {
+        debug!("[Geometry] ScreenCast results have no streams");
+        return Vec::new();
+    };
+
+    let point = |properties: &HashMap<String, OwnedValue>, key: &str| {
+        properties
+            .get(key)
+            .and_then(|value| value.try_clone().ok())
+            .and_then(|value| <(i32, i32)>::try_from(value).ok())
+    };
+
+    streams
+        .into_iter()
+        .filter_map(|(id, properties)| {
+            let Some((width, height)) = point(&properties, "size") else {
+                debug!("[Geometry] Stream {} has no size, skipping it", id);
+                return None;
+            };
+            if width <= 0 || height <= 0 {
+                debug!(
+                    "[Geometry] Stream {} is {}x{}, skipping it",
+                    id, width, height
+                );
+                return None;
+            }
+            let (x, y) = point(&properties, "position").unwrap_or((0, 0));
+            Some(Stream {
+                id,
+                rect: Rect {
+                    x,
+                    y,
+                    width,
+                    height,
+                },
+            })
+        })
+        .collect()
+}
+
+/// Connected DRM outputs at their preferred mode, the fallback for sessions
+/// without ScreenCast streams, whose properties give the logical layout.
+///
+/// The rectangles are in physical pixels: sysfs knows neither the scale nor the
+/// position the compositor gives an output, so the outputs are assumed to be
+/// unscaled and placed left to right in connector order.
+pub fn drm_outputs() -> Vec<Rect> {
+    let mut connectors = fs::read_dir("/sys/class/drm")
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| path.join("status").exists())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    connectors.sort();
+
+    let mut outputs = Vec::new();
+    let mut x = 0;
+    for connector in connectors {
+        if let Some((width, height)) = preferred_mode(&connector) {
+            debug!(
+                "[Geometry] Output {} is {}x{} at {}",
+                connector.display(),
+                width,
+                height,
+                x
+            );
+            outputs.push(Rect {
+                x,
+                y: 0,
+                width,
+                height,
+            });
+            x += width;
+        }
+    }
+
+    if outputs.is_empty() {
+        debug!("[Geometry] No connected output found, assuming {FALLBACK_OUTPUT:?}");
+        outputs.push(FALLBACK_OUTPUT);
+    }
+
+    outputs
+}
+
+fn preferred_mode(connector: &Path) -> Option<(i32, i32)> {
+    let status = fs::read_to_string(connector.join("status")).ok()?;
+    if status.trim() != "connected" {
+        return None;
+    }
+
+    // The first mode listed is the preferred one, e.g. "1920x1080".
+    let modes = fs::read_to_string(connector.join("modes")).ok()?;
+    let (width, height) = modes.lines().next()?.split_once('x')?;
+    Some((
+        width.parse().ok()?,
+        height.trim_end_matches('i').parse().ok()?,
+    ))
+}
+
+/// Maps positions inside a stream onto the units of an absolute pointer device
+/// spanning the whole desktop.
+#[derive(Debug, Clone, Copy)]
+pub struct AbsoluteMapping {
+    desktop: Rect,
+}
+
+impl AbsoluteMapping {
+    pub fn new(streams: &[Rect]) -> Self {
+        let desktop = streams
+            .iter()
+            .copied()
+            .reduce(Rect::union)
+            .unwrap_or(FALLBACK_OUTPUT);
+        Self { desktop }
+    }
+
+    /// Largest value of the `ABS_X` and `ABS_Y` axes, one unit per logical pixel.
+    pub fn maximum(&self) -> (i32, i32) {
+        (last(self.desktop.width), last(self.desktop.height))
+    }
+
+    /// Translate `x`, `y` in logical pixels relative to `stream` into device units.
+    pub fn device_position(&self, stream: Rect, x: f64, y: f64) -> (i32, i32) {
+        let (max_x, max_y) = self.maximum();
+        let x = (stream.x - self.desktop.x) as f64 + x.clamp(0.0, last(stream.width) as f64);
+        let y = (stream.y - self.desktop.y) as f64 + y.clamp(0.0, last(stream.height) as f64);
+
+        (
+            (x.round() as i32).clamp(0, max_x),
+            (y.round() as i32).clamp(0, max_y),
+        )
+    }
+}
+
+/// Last position along a side `length` long, 0 for an empty side.
+fn last(length: i32) -> i32 {
+    (length - 1).max(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEFT: Rect = Rect {
+        x: 0,
+        y: 0,
+        width: 1920,
+        height: 1080,
+    };
+    const RIGHT: Rect = Rect {
+        x: 1920,
+        y: 0,
+        width: 2560,
+        height: 1440,
+    };
+
+    #[test]
+    fn streams_are_read_from_results() {
+        let value = |value: zbus::zvariant::Value| OwnedValue::try_from(value).unwrap();
+        let stream = |position: Option<(i32, i32)>| {
+            let mut properties = HashMap::from([("size".to_string(), value((2560, 1440).into()))]);
+            if let Some(position) = position {
+                properties.insert("position".to_string(), value(position.into()));
+            }
+            properties
+        };
+        let mut empty = stream(None);
+        empty.insert("size".to_string(), value((0, 1440).into()));
+        let streams = vec![
+            (44u32, stream(None)),
+            (45u32, stream(Some((2560, 0)))),
+            (46u32, empty),
+        ];
+        let results = HashMap::from([("streams".to_string(), value(streams.into()))]);
+
+        assert_eq!(
+            screen_cast_streams(&results),
+            vec![
+                Stream {
+                    id: 44,
+                    rect: Rect {
+                        x: 0,
+                        y: 0,
+                        ..RIGHT
+                    },
+                },
+                Stream {
+                    id: 45,
+                    rect: Rect { x: 2560, ..RIGHT },
+                },
+            ]
+        );
+        assert!(screen_cast_streams(&HashMap::new()).is_empty());
+    }
+
+    #[test]
+    fn desktop_spans_every_stream() {
+        let mapping = AbsoluteMapping::new(&[LEFT, RIGHT]);
+        assert_eq!(mapping.maximum(), (4479, 1439));
+    }
+
+    #[test]
+    fn positions_are_offset_by_stream() {
+        let mapping = AbsoluteMapping::new(&[LEFT, RIGHT]);
+        assert_eq!(mapping.device_position(LEFT, 100.0, 200.0), (100, 200));
+        assert_eq!(mapping.device_position(RIGHT, 100.0, 200.0), (2020, 200));
+    }
+
+    #[test]
+    fn positions_are_clamped_to_stream() {
+        let mapping = AbsoluteMapping::new(&[LEFT, RIGHT]);
+        assert_eq!(mapping.device_position(LEFT, -5.0, 5000.0), (0, 1079));
+        assert_eq!(mapping.device_position(RIGHT, 9000.0, 0.0), (4479, 0));
+    }
+
+    #[test]
+    fn empty_rectangles_map_to_their_origin() {
+        let empty = Rect {
+            width: 0,
+            height: -1,
+            ..LEFT
+        };
+        let mapping = AbsoluteMapping::new(&[empty]);
+        assert_eq!(mapping.maximum(), (0, 0));
+        assert_eq!(mapping.device_position(empty, 10.0, 10.0), (0, 0));
+    }
+}
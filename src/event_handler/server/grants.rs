@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+
+use tracing::debug;
+use zbus::proxy;
+use zbus::zvariant::OwnedValue;
+
+/// Table of the permission store holding RemoteDesktop grants.
+const TABLE: &str = "remote-desktop";
+/// Resource the grants of the table are stored under, one entry per app id.
+const ID: &str = "xdg-desktop-portal-bypass";
+/// Table of the restore tokens issued with `persist_mode` 2, one entry per
+/// token holding the app id and its device types.
+const TOKEN_TABLE: &str = "remote-desktop-restore";
+
+/// Device types restored from a token, for the app the token was issued to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Restore {
+    pub app_id: String,
+    pub device_types: u32,
+}
+
+#[proxy(
+    interface = "org.freedesktop.impl.portal.PermissionStore",
+    default_service = "org.freedesktop.impl.portal.PermissionStore",
+    default_path = "/org/freedesktop/impl/portal/PermissionStore"
+)]
+trait PermissionStoreProxySenderTrait {
+    fn lookup(
+        &self,
+        table: &str,
+        id: &str,
+    ) -> zbus::fdo::Result<(HashMap<String, Vec<String>>, OwnedValue)>;
+
+    fn set_permission(
+        &self,
+        table: &str,
+        create: bool,
+        id: &str,
+        app: &str,
+        permissions: &[&str],
+    ) -> zbus::fdo::Result<()>;
+
+    fn delete_permission(&self, table: &str, id: &str, app: &str) -> zbus::fdo::Result<()>;
+
+    fn delete(&self, table: &str, id: &str) -> zbus::fdo::Result<()>;
+
+    fn list(&self, table: &str) -> zbus::fdo::Result<Vec<String>>;
+}
+
+/// Permissions of every app in the entry `id` of `table`, none if the store
+/// doesn't know it.
+async fn lookup_entry(
+    store: &PermissionStoreProxySenderTraitProxy<'_>,
+    table: &str,
+    id: &str,
+) -> zbus::fdo::Result<HashMap<String, Vec<String>>> {
+    match store.lookup(table, id).await {
+        Ok((apps, _)) => Ok(apps),
+        Err(zbus::fdo::Error::ZBus(zbus::Error::MethodError(name, description, _)))
+            if name.as_str() == "org.freedesktop.portal.Error.NotFound" =>
+        {
+            debug!("[Grants] No entry {} in {}: {:?}", id, table, description);
+            Ok(HashMap::new())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Every stored grant, the app ids with the device types they may create.
+pub async fn list(connection: &zbus::Connection) -> zbus::fdo::Result<HashMap<String, u32>> {
+    let store = PermissionStoreProxySenderTraitProxy::new(connection).await?;
+    Ok(lookup_entry(&store, TABLE, ID)
+        .await?
+        .into_iter()
+        .filter_map(|(app_id, permissions)| Some((app_id, granted_types(&permissions)?)))
+        .collect())
+}
+
+/// Device types `app_id` was granted until revoked, if any.
+pub async fn lookup(connection: &zbus::Connection, app_id: &str) -> zbus::fdo::Result<Option<u32>> {
+    Ok(list(connection).await?.get(app_id).copied())
+}
+
+/// Remember that `app_id` may create `device_types` without asking again.
+pub async fn grant(
+    connection: &zbus::Connection,
+    app_id: &str,
+    device_types: u32,
+) -> zbus::fdo::Result<()> {
+    let store = PermissionStoreProxySenderTraitProxy::new(connection).await?;
+    let permissions = permissions(device_types);
+    let permissions: Vec<&str> = permissions.iter().map(String::as_str).collect();
+    store
+        .set_permission(TABLE, true, ID, app_id, &permissions)
+        .await
+}
+
+/// Forget the grant and the stored restore tokens of `app_id`, its next
+/// session asks again.
+pub async fn revoke(connection: &zbus::Connection, app_id: &str) -> zbus::fdo::Result<()> {
+    let store = PermissionStoreProxySenderTraitProxy::new(connection).await?;
+    if lookup_entry(&store, TABLE, ID).await?.contains_key(app_id) {
+        store.delete_permission(TABLE, ID, app_id).await?;
+    }
+    delete_tokens(&store, app_id).await
+}
+
+/// Delete every stored restore token of `app_id`.
+async fn delete_tokens(
+    store: &PermissionStoreProxySenderTraitProxy<'_>,
+    app_id: &str,
+) -> zbus::fdo::Result<()> {
+    let tokens = match store.list(TOKEN_TABLE).await {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            debug!("[Grants] No restore tokens stored: {}", e);
+            Vec::new()
+        }
+    };
+    for token in tokens {
+        if lookup_entry(store, TOKEN_TABLE, &token)
+            .await?
+            .contains_key(app_id)
+        {
+            store.delete(TOKEN_TABLE, &token).await?;
+        }
+    }
+    Ok(())
+}
+
+/// A new random restore token.
+pub fn new_token() -> io::Result<String> {
+    let mut bytes = [0u8; 16];
+    File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// Whether `token` looks like one from [`new_token`], anything else is never
+/// looked up.
+pub fn is_token(token: &str) -> bool {
+    token.len() == 32 && token.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
+/// Keep the restore token `token` of `app_id` until it is used, in place of
+/// the tokens issued to the app before.
+pub async fn store_token(
+    connection: &zbus::Connection,
+    token: &str,
+    restore: &Restore,
+) -> zbus::fdo::Result<()> {
+    let store = PermissionStoreProxySenderTraitProxy::new(connection).await?;
+    delete_tokens(&store, &restore.app_id).await?;
+    let permissions = permissions(restore.device_types);
+    let permissions: Vec<&str> = permissions.iter().map(String::as_str).collect();
+    store
+        .set_permission(TOKEN_TABLE, true, token, &restore.app_id, &permissions)
+        .await
+}
+
+/// Device types restored by `token` for `app_id`. A token issued to `app_id`
+/// is removed, it can only be used once.
+pub async fn take_token(
+    connection: &zbus::Connection,
+    token: &str,
+    app_id: &str,
+) -> zbus::fdo::Result<Option<u32>> {
+    let store = PermissionStoreProxySenderTraitProxy::new(connection).await?;
+    let apps = lookup_entry(&store, TOKEN_TABLE, token).await?;
+    // Other apps guessing the token must not use it up.
+    let Some(permissions) = apps.get(app_id) else {
+        return Ok(None);
+    };
+    store.delete(TOKEN_TABLE, token).await?;
+    Ok(granted_types(permissions))
+}
+
+/// Permissions stored for a grant of `device_types`, e.g. `["yes", "types=3"]`.
+fn permissions(device_types: u32) -> Vec<String> {
+    vec!["yes".to_string(), format!("types={}", device_types)]
+}
+
+/// Device types of stored permissions, `None` unless they grant anything.
+fn granted_types(permissions: &[String]) -> Option<u32> {
+    if !permissions.iter().any(|permission| permission == "yes") {
+        return None;
+    }
+    permissions
+        .iter()
+        .find_map(|permission| permission.strip_prefix("types="))
+        .and_then(|types| types.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn permissions_round_trip() {
+        assert_eq!(granted_types(&permissions(5)), Some(5));
+        assert_eq!(
+            granted_types(&["no".to_string(), "types=3".to_string()]),
+            None
+        );
+        assert_eq!(granted_types(&["yes".to_string()]), None);
+    }
+
+    #[test]
+    fn tokens_are_random_hex() {
+        let token = new_token().unwrap();
+        assert!(is_token(&token));
+        assert_ne!(token, new_token().unwrap());
+        assert!(!is_token("../../etc"));
+    }
+}
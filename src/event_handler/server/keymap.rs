@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::ptr;
+
+use anyhow::Context;
+use evdev::KeyCode;
+use xkbcommon_dl::{
+    XKB_MOD_INVALID, XKB_MOD_NAME_SHIFT, xkb_context_flags, xkb_keymap_compile_flags,
+    xkb_rule_names, xkbcommon_option,
+};
+
+/// evdev keycodes are offset by 8 in XKB.
+const EVDEV_OFFSET: u32 = 8;
+
+/// Real modifier AltGr (level three) is bound to in every common layout.
+const XKB_MOD_NAME_LEVEL_THREE: &[u8] = b"Mod5\0";
+
+/// An evdev key and the modifiers that have to be held to produce a keysym.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyCombo {
+    pub keycode: KeyCode,
+    pub shift: bool,
+    pub level_three: bool,
+}
+
+/// Reverse lookup from keysyms to the keys producing them in a XKB keymap.
+#[derive(Debug, Default)]
+pub struct KeysymMap {
+    keys: HashMap<u32, KeyCombo>,
+}
+
+impl KeysymMap {
+    /// Compile the keymap for `layout` and `variant`, falling back to the
+    /// `XKB_DEFAULT_*` environment and then the system default for unset names.
+    pub fn new(layout: Option<&str>, variant: Option<&str>) -> anyhow::Result<Self> {
+        let xkb = xkbcommon_option().with_context(|| "libxkbcommon could not be loaded")?;
+
+        let layout = layout.map(CString::new).transpose()?;
+        let variant = variant.map(CString::new).transpose()?;
+        let names = xkb_rule_names {
+            rules: ptr::null(),
+            model: ptr::null(),
+            layout: layout.as_ref().map_or(ptr::null(), |s| s.as_ptr()),
+            variant: variant.as_ref().map_or(ptr::null(), |s| s.as_ptr()),
+            options: ptr::null(),
+        };
+
+        let mut keys = HashMap::new();
+
+        // SAFETY: every object is checked for null after creation, only used while
+        // alive and released before returning. `names` outlives the compile call.
+        unsafe {
+            let context = (xkb.xkb_context_new)(xkb_context_flags::XKB_CONTEXT_NO_FLAGS);
+            if context.is_null() {
+                anyhow::bail!("Failed to create XKB context");
+            }
+
+            let keymap = (xkb.xkb_keymap_new_from_names)(
+                context,
+                &names,
+                xkb_keymap_compile_flags::XKB_KEYMAP_COMPILE_NO_FLAGS,
+            );
+            if keymap.is_null() {
+                (xkb.xkb_context_unref)(context);
+                anyhow::bail!("Failed to compile XKB keymap");
+            }
+
+            let state = (xkb.xkb_state_new)(keymap);
+            if state.is_null() {
+                (xkb.xkb_keymap_unref)(keymap);
+                (xkb.xkb_context_unref)(context);
+                anyhow::bail!("Failed to create XKB state");
+            }
+
+            let mod_mask = |name: &[u8]| {
+                let index = (xkb.xkb_keymap_mod_get_index)(keymap, name.as_ptr().cast());
+                (index != XKB_MOD_INVALID).then(|| 1u32 << index)
+            };
+            let shift = mod_mask(XKB_MOD_NAME_SHIFT);
+            let level_three = mod_mask(XKB_MOD_NAME_LEVEL_THREE);
+
+            // Try the cheapest modifier combination first so a keysym reachable
+            // without modifiers never gets an extra Shift or AltGr.
+            let mut combinations = vec![(0, false, false)];
+            if let Some(shift) = shift {
+                combinations.push((shift, true, false));
+            }
+            if let Some(level_three) = level_three {
+                combinations.push((level_three, false, true));
+                if let Some(shift) = shift {
+                    combinations.push((shift | level_three, true, true));
+                }
+            }
+            let min_keycode = (xkb.xkb_keymap_min_keycode)(keymap).max(EVDEV_OFFSET);
+            let max_keycode = (xkb.xkb_keymap_max_keycode)(keymap);
+
+            for (mask, with_shift, with_level_three) in combinations {
+                (xkb.xkb_state_update_mask)(state, mask, 0, 0, 0, 0, 0);
+
+                for keycode in min_keycode..=max_keycode {
+                    let keysym = (xkb.xkb_state_key_get_one_sym)(state, keycode);
+                    if keysym == 0 {
+                        continue;
+                    }
+                    keys.entry(keysym).or_insert(KeyCombo {
+                        keycode: KeyCode::new((keycode - EVDEV_OFFSET) as u16),
+                        shift: with_shift,
+                        level_three: with_level_three,
+                    });
+                }
+            }
+
+            (xkb.xkb_state_unref)(state);
+            (xkb.xkb_keymap_unref)(keymap);
+            (xkb.xkb_context_unref)(context);
+        }
+
+        Ok(Self { keys })
+    }
+
+    pub fn lookup(&self, keysym: u32) -> Option<KeyCombo> {
+        self.keys.get(&keysym).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[ignore = "needs libxkbcommon and its keymap data"]
+    fn us_layout_keysyms() {
+        let map = KeysymMap::new(Some("us"), None).unwrap();
+
+        let plain = |keycode| KeyCombo {
+            keycode,
+            shift: false,
+            level_three: false,
+        };
+        assert_eq!(map.lookup(0x61), Some(plain(KeyCode::KEY_A))); // a
+        assert_eq!(map.lookup(0xff0d), Some(plain(KeyCode::KEY_ENTER))); // Return
+        assert_eq!(
+            map.lookup(0x41), // A
+            Some(KeyCombo {
+                shift: true,
+                ..plain(KeyCode::KEY_A)
+            })
+        );
+    }
+}
@@ -1 +1,7 @@
+pub mod consent;
+pub mod eis;
+pub mod geometry;
+pub mod grants;
+pub mod keymap;
 pub mod remote_desktop;
+pub mod touch;
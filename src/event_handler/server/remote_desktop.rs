@@ -1,28 +1,640 @@
 use std::collections::HashMap;
+use std::collections::HashSet;
 
 use anyhow::Context;
+use calloop::LoopHandle;
+use calloop::RegistrationToken;
+use evdev::AbsInfo;
+use evdev::AbsoluteAxisCode;
 use evdev::AttributeSet;
 use evdev::EventType;
 use evdev::InputEvent;
 use evdev::KeyCode;
 use evdev::RelativeAxisCode;
+use evdev::UinputAbsSetup;
 use evdev::uinput::VirtualDevice;
+use futures::channel::oneshot;
 use tracing::debug;
 use tracing::error;
+use tracing::info;
 use zbus::zvariant;
 use zbus::zvariant::OwnedValue;
-use zbus::zvariant::Value;
 
+use crate::event_handler::ConsentAnswered;
+use crate::event_handler::ConsentOptions;
+use crate::event_handler::DeviceOptions;
 use crate::event_handler::Event;
 use crate::event_handler::EventHandle;
 use crate::event_handler::EventHandler;
 use crate::event_handler::EventResponse;
+use crate::event_handler::Interface;
+use crate::event_handler::SelectionRestored;
+use crate::event_handler::StreamsStarted;
+use crate::event_handler::XdgBypass;
+use crate::event_handler::empty_results;
+use crate::event_handler::events::Payload;
 use crate::event_handler::events::remote_desktop::RemoteDesktopEvent;
+use crate::event_handler::events::screen_cast::ScreenCastEvent;
+use crate::event_handler::policy::Access;
+use crate::event_handler::policy::DEVICE_TYPES;
+use crate::event_handler::proxy::DownstreamPaths;
+use crate::event_handler::proxy::screen_cast::ScreenCastProxy;
 use crate::event_handler::return_response;
+use crate::event_handler::server::consent;
+use crate::event_handler::server::eis;
+use crate::event_handler::server::geometry;
+use crate::event_handler::server::geometry::{AbsoluteMapping, Rect, Stream};
+use crate::event_handler::server::grants;
+use crate::event_handler::server::keymap::{KeyCombo, KeysymMap};
+use crate::event_handler::server::touch::Touchscreen;
 
+/// Device types this backend can provide: keyboard (1), pointer (2) and touchscreen (4).
+const AVAILABLE_DEVICE_TYPES: u32 = 7;
+
+/// Serves one RemoteDesktop session through its own uinput devices.
 pub struct RemoteDesktopServer {
-    device_select: u32,
+    session_handle: zvariant::OwnedObjectPath,
+    app_id: String,
+    /// Bitmask of the device types selected in `SelectDevices`.
+    device_types: u32,
+    /// `persist_mode` requested in `SelectDevices`: 0 none, 1 while running, 2 until revoked.
+    persist_mode: u32,
+    /// Whether `SelectDevices` restored the device types from a restore
+    /// token, `Start` then goes on without a prompt.
+    restored: bool,
+    /// Created on `Start` and owned by this session only, dropped on `Close`.
     device: Option<VirtualDevice>,
+    /// Separate device for `NotifyPointerMotionAbsolute`, with the mapping of
+    /// stream positions onto its axes.
+    absolute_pointer: Option<(VirtualDevice, AbsoluteMapping)>,
+    touchscreen: Option<Touchscreen>,
+    /// Keysym lookup for `NotifyKeyboardKeysym`, compiled on `Start`.
+    keysyms: Option<KeysymMap>,
+    held_keys: HeldKeys,
+    /// Device options of the configuration the session was created with.
+    options: DeviceOptions,
+    /// Geometry of the streams absolute positions refer to. These are the
+    /// ScreenCast streams when sources were selected, the connected outputs otherwise.
+    streams: Vec<Stream>,
+    /// EIS socket served on the event loop once `ConnectToEIS` was called.
+    eis: Option<RegistrationToken>,
+    /// Loop serving `eis`, removing it when the session goes away.
+    loop_handle: LoopHandle<'static, XdgBypass>,
+    /// ScreenCast session at the proxied backend, created when the client
+    /// selects sources on this session.
+    screen_cast: Option<ScreenCastProxy>,
+    /// Consent options of the configuration the session was created with.
+    consent: ConsentOptions,
+    /// Request handle of the `Start` waiting for the user, and the sender
+    /// withdrawing its prompt.
+    pending_consent: Option<(zvariant::ObjectPath<'static>, oneshot::Sender<()>)>,
+}
+
+impl Drop for RemoteDesktopServer {
+    fn drop(&mut self) {
+        // Sessions the destination ended are dropped without a `Close`.
+        if let Some(token) = self.eis.take() {
+            self.loop_handle.remove(token);
+        }
+    }
+}
+
+impl RemoteDesktopServer {
+    /// Create the uinput devices for the selected device types.
+    fn build_devices(&mut self) -> anyhow::Result<()> {
+        // Keyboards and relative pointers share one device, named after its
+        // app so it can be told apart.
+        let device = match self.device_types & 3 != 0 {
+            true => Some(self.build_device()?),
+            false => None,
+        };
+
+        // Without ScreenCast streams the layout can only be guessed from DRM.
+        if self.device_types & 6 != 0 && self.streams.is_empty() {
+            self.streams = geometry::drm_outputs()
+                .into_iter()
+                .zip(0..)
+                .map(|(rect, id)| Stream { id, rect })
+                .collect();
+        }
+        let rects = self
+            .streams
+            .iter()
+            .map(|stream| stream.rect)
+            .collect::<Vec<_>>();
+        let mapping = AbsoluteMapping::new(&rects);
+
+        let absolute_pointer = if self.device_types & 2 != 0 {
+            Some((self.build_absolute_pointer(&mapping)?, mapping))
+        } else {
+            None
+        };
+
+        let touchscreen = if self.device_types & 4 != 0 {
+            let name = format!(
+                "xdg-desktop-portal-bypass virtual touchscreen ({})",
+                self.app_id
+            );
+            Some(Touchscreen::new(&name, mapping)?)
+        } else {
+            None
+        };
+
+        if self.device_types & 1 != 0 {
+            self.keysyms = KeysymMap::new(
+                self.options.keyboard_layout.as_deref(),
+                self.options.keyboard_variant.as_deref(),
+            )
+            .map_err(|e| error!("[RemoteDesktop.Start] Keysyms are unavailable: {:#}", e))
+            .ok();
+        }
+
+        self.device = device;
+        self.absolute_pointer = absolute_pointer;
+        self.touchscreen = touchscreen;
+        Ok(())
+    }
+
+    /// Device with the keys and relative axes of the selected keyboard and
+    /// pointer.
+    fn build_device(&self) -> anyhow::Result<VirtualDevice> {
+        let mut keys = AttributeSet::<KeyCode>::new();
+        if self.device_types & 1 != 0 {
+            for i in 0x000..0x2e7 {
+                keys.insert(KeyCode::new(i as u16));
+            }
+        }
+        let mut mouse_axis = AttributeSet::<RelativeAxisCode>::new();
+        if self.device_types & 2 != 0 {
+            for i in KeyCode::BTN_LEFT.code()..=KeyCode::BTN_TASK.code() {
+                keys.insert(KeyCode::new(i));
+            }
+            for i in 0x00..0x0c {
+                mouse_axis.insert(RelativeAxisCode(i as u16));
+            }
+        }
+
+        let name = format!(
+            "xdg-desktop-portal-bypass virtual input device ({})",
+            self.app_id
+        );
+        VirtualDevice::builder()?
+            .name(&name)
+            .with_keys(&keys)?
+            .with_relative_axes(&mouse_axis)?
+            .build()
+            .with_context(|| "Failed to create virtual device.")
+    }
+
+    /// Whether `Start` created the devices of any selected type.
+    fn has_devices(&self) -> bool {
+        self.device.is_some() || self.absolute_pointer.is_some() || self.touchscreen.is_some()
+    }
+
+    /// Absolute pointer covering the whole desktop, one unit per logical pixel.
+    fn build_absolute_pointer(&self, mapping: &AbsoluteMapping) -> anyhow::Result<VirtualDevice> {
+        // Buttons make it an absolute mouse rather than a touchscreen or tablet.
+        let mut buttons = AttributeSet::<KeyCode>::new();
+        buttons.insert(KeyCode::BTN_LEFT);
+        buttons.insert(KeyCode::BTN_RIGHT);
+        buttons.insert(KeyCode::BTN_MIDDLE);
+
+        let (max_x, max_y) = mapping.maximum();
+        let name = format!(
+            "xdg-desktop-portal-bypass virtual absolute pointer ({})",
+            self.app_id
+        );
+        VirtualDevice::builder()?
+            .name(&name)
+            .with_keys(&buttons)?
+            .with_absolute_axis(&UinputAbsSetup::new(
+                AbsoluteAxisCode::ABS_X,
+                AbsInfo::new(0, 0, max_x, 0, 0, 1),
+            ))?
+            .with_absolute_axis(&UinputAbsSetup::new(
+                AbsoluteAxisCode::ABS_Y,
+                AbsInfo::new(0, 0, max_y, 0, 0, 1),
+            ))?
+            .build()
+            .with_context(|| "Failed to create virtual absolute pointer.")
+    }
+
+    fn stream(&self, id: u32) -> Option<Rect> {
+        self.streams
+            .iter()
+            .find(|stream| stream.id == id)
+            .map(|stream| stream.rect)
+    }
+
+    /// Results of `Start`: the selected devices, plus the streams of the
+    /// ScreenCast session if there is one, and a fresh restore token when
+    /// the session should be persisted.
+    fn start_results(&self, xdg_bypass: &mut XdgBypass, streams: Option<OwnedValue>) -> OwnedValue {
+        let mut results =
+            HashMap::from([("devices".to_string(), OwnedValue::from(self.device_types))]);
+        if let Some(streams) = streams {
+            results.insert("streams".to_string(), streams);
+        }
+        if let Some(token) = self.issue_restore_token(xdg_bypass) {
+            results.insert(
+                "persist_mode".to_string(),
+                OwnedValue::from(self.persist_mode),
+            );
+            results.insert(
+                "restore_token".to_string(),
+                OwnedValue::from(zvariant::Str::from(token)),
+            );
+        }
+        OwnedValue::from(results)
+    }
+
+    /// A new restore token for the device selection of this session, kept as
+    /// long as `persist_mode` asks. Every `Start` issues another one, which
+    /// replaces the tokens issued to the app before.
+    fn issue_restore_token(&self, xdg_bypass: &mut XdgBypass) -> Option<String> {
+        if self.persist_mode == 0 || self.app_id.is_empty() {
+            return None;
+        }
+        let token = grants::new_token()
+            .map_err(|e| {
+                error!(
+                    "[RemoteDesktop.Start] Failed to create a restore token: {}",
+                    e
+                )
+            })
+            .ok()?;
+        let restore = grants::Restore {
+            app_id: self.app_id.clone(),
+            device_types: self.device_types,
+        };
+
+        xdg_bypass
+            .restore_tokens
+            .retain(|_, issued| issued.app_id != self.app_id);
+        match self.persist_mode {
+            1 => {
+                xdg_bypass.restore_tokens.insert(token.clone(), restore);
+            }
+            _ => {
+                let connection = xdg_bypass.connection.clone();
+                let stored = token.clone();
+                let _ = xdg_bypass
+                    .scheduler
+                    .schedule(async move {
+                        if let Err(e) = grants::store_token(&connection, &stored, &restore).await {
+                            error!(
+                                "[RemoteDesktop.Start] Failed to store the restore token of {}: {}",
+                                restore.app_id, e
+                            );
+                        }
+                    })
+                    .map_err(|e| {
+                        error!("[RemoteDesktop.Start] Failed to schedule the token: {}", e)
+                    });
+            }
+        }
+        Some(token)
+    }
+
+    /// Device types the session's policy rule allows, restored selections are
+    /// limited to them.
+    fn allowed_device_types(&self, xdg_bypass: &XdgBypass) -> u32 {
+        xdg_bypass
+            .policies
+            .rule(&self.session_handle)
+            .map_or(DEVICE_TYPES, |rule| rule.device_types)
+    }
+
+    /// Restore the device types of `token` for `SelectDevices`, then answer
+    /// it. Tokens kept in the permission store come back as
+    /// [`Event::SelectionRestored`].
+    fn restore_selection(
+        &mut self,
+        xdg_bypass: &mut XdgBypass,
+        token: String,
+        to_return: oneshot::Sender<EventResponse>,
+    ) -> anyhow::Result<()> {
+        // Tokens are single use, whether they restore anything or not.
+        if let Some(restore) = xdg_bypass.restore_tokens.remove(&token) {
+            if restore.app_id == self.app_id {
+                self.restore(xdg_bypass, restore.device_types);
+            }
+            return_response(
+                to_return,
+                EventResponse::Standard(0, empty_results()),
+                "RemoteDesktop.SelectDevices",
+            );
+            return Ok(());
+        }
+
+        let connection = xdg_bypass.connection.clone();
+        let app_id = self.app_id.clone();
+        let lookup = async move {
+            match grants::take_token(&connection, &token, &app_id).await {
+                Ok(device_types) => device_types,
+                Err(e) => {
+                    error!(
+                        "[RemoteDesktop.SelectDevices] Failed to look up the token: {}",
+                        e
+                    );
+                    None
+                }
+            }
+        };
+        let session = self.session_handle.clone();
+        xdg_bypass
+            .schedule_then(lookup, move |xdg_bypass, device_types| {
+                xdg_bypass.dispatch(EventHandle {
+                    session,
+                    event: Event::SelectionRestored(SelectionRestored { device_types }),
+                    return_tx: to_return,
+                });
+            })
+            .with_context(|| "Failed to look up the restore token")
+    }
+
+    /// Take over the device types of a restore token, as far as the policy
+    /// still allows them.
+    fn restore(&mut self, xdg_bypass: &XdgBypass, device_types: u32) {
+        info!(
+            "[RemoteDesktop.SelectDevices] Restored the devices of {} from its token.",
+            self.app_id
+        );
+        self.device_types = device_types & self.allowed_device_types(xdg_bypass);
+        self.restored = true;
+    }
+
+    /// Ask the user to approve `start` before any device exists, unless the
+    /// policy allows the app, it was granted the device types before or
+    /// restored them from a token. The answer comes back as
+    /// [`Event::ConsentAnswered`].
+    fn request_consent(
+        &mut self,
+        xdg_bypass: &mut XdgBypass,
+        start: crate::event_handler::events::remote_desktop::Start,
+        to_return: oneshot::Sender<EventResponse>,
+    ) -> anyhow::Result<()> {
+        if self.restored {
+            return self.start(xdg_bypass, start, to_return);
+        }
+        let approved = xdg_bypass
+            .policies
+            .rule(&self.session_handle)
+            .is_some_and(|rule| rule.access == Access::Allow);
+        if approved {
+            info!(
+                "[RemoteDesktop.Start] {} is allowed by the policy.",
+                self.app_id
+            );
+            return self.start(xdg_bypass, start, to_return);
+        }
+
+        // Grants are never remembered for apps without an app id, that would
+        // grant every unsandboxed process at once.
+        let remember = !self.app_id.is_empty();
+        let device_types = self.device_types;
+        let covers = move |granted: u32| device_types & !granted == 0;
+        if remember
+            && xdg_bypass
+                .transient_grants
+                .get(&self.app_id)
+                .is_some_and(|granted| covers(*granted))
+        {
+            info!(
+                "[RemoteDesktop.Start] {} was granted these devices while running.",
+                self.app_id
+            );
+            return self.start(xdg_bypass, start, to_return);
+        }
+
+        let (cancel_tx, cancel_rx) = oneshot::channel();
+        self.pending_consent = Some((start.handle.clone(), cancel_tx));
+
+        let prompt = consent::Prompt::new(&self.app_id, self.device_types);
+        let connection = xdg_bypass.connection.clone();
+        let options = self.consent.clone();
+        let answer = async move {
+            let granted = remember && stored_grant(&connection, &prompt.app_id, covers).await;
+            match granted {
+                true => consent::Answer::Granted,
+                false => match consent::ask(connection, prompt, options, cancel_rx).await {
+                    true => consent::Answer::Approved,
+                    false => consent::Answer::Denied,
+                },
+            }
+        };
+        let session = self.session_handle.clone();
+        xdg_bypass
+            .schedule_then(answer, move |xdg_bypass, answer| {
+                xdg_bypass.dispatch(EventHandle {
+                    session,
+                    event: Event::ConsentAnswered(ConsentAnswered { start, answer }),
+                    return_tx: to_return,
+                });
+            })
+            .with_context(|| "Failed to ask for the user's consent")
+    }
+
+    /// Remember the approved device types as `persist_mode` asks: while the
+    /// daemon runs for 1, in the permission store until revoked for 2.
+    fn remember_grant(&self, xdg_bypass: &mut XdgBypass) {
+        match self.persist_mode {
+            1 => {
+                *xdg_bypass
+                    .transient_grants
+                    .entry(self.app_id.clone())
+                    .or_default() |= self.device_types;
+            }
+            2 => {
+                let connection = xdg_bypass.connection.clone();
+                let app_id = self.app_id.clone();
+                let device_types = self.device_types;
+                let _ = xdg_bypass
+                    .scheduler
+                    .schedule(async move {
+                        match grants::grant(&connection, &app_id, device_types).await {
+                            Ok(()) => info!(
+                                "[RemoteDesktop.Start] Granted {} device types {} until revoked.",
+                                app_id, device_types
+                            ),
+                            Err(e) => error!(
+                                "[RemoteDesktop.Start] Failed to store the grant of {}: {}",
+                                app_id, e
+                            ),
+                        }
+                    })
+                    .map_err(|e| {
+                        error!("[RemoteDesktop.Start] Failed to schedule the grant: {}", e)
+                    });
+            }
+            _ => {}
+        }
+    }
+
+    /// Go on with an approved `Start`: build the devices, after starting the
+    /// ScreenCast session if sources were selected.
+    fn start(
+        &mut self,
+        xdg_bypass: &mut XdgBypass,
+        start: crate::event_handler::events::remote_desktop::Start,
+        to_return: oneshot::Sender<EventResponse>,
+    ) -> anyhow::Result<()> {
+        if self.screen_cast.is_some() {
+            debug!("[RemoteDesktop.Start] Starting the ScreenCast session first.");
+            return self.start_streams(xdg_bypass, start, to_return);
+        }
+
+        debug!("[RemoteDesktop.Start] Try to build virtual device.");
+        match self.build_devices() {
+            Ok(()) => {
+                return_response(
+                    to_return,
+                    EventResponse::Standard(0, self.start_results(xdg_bypass, None)),
+                    "RemoteDesktop.Start",
+                );
+            }
+            Err(e) => {
+                error!("[RemoteDesktop.Start] {:#}", e);
+                return_response(
+                    to_return,
+                    EventResponse::Standard(2, empty_results()),
+                    "RemoteDesktop.Start",
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Start the proxied ScreenCast session first. Its answer comes back as
+    /// [`Event::StreamsStarted`], which builds the devices on the streams.
+    fn start_streams(
+        &mut self,
+        xdg_bypass: &mut XdgBypass,
+        start: crate::event_handler::events::remote_desktop::Start,
+        to_return: oneshot::Sender<EventResponse>,
+    ) -> anyhow::Result<()> {
+        let Some(screen_cast) = &mut self.screen_cast else {
+            anyhow::bail!("No ScreenCast session to start");
+        };
+
+        let (return_tx, return_rx) = oneshot::channel();
+        screen_cast.handle(
+            xdg_bypass,
+            EventHandle {
+                session: self.session_handle.clone(),
+                event: Event::ScreenCast(ScreenCastEvent::Start(
+                    crate::event_handler::events::screen_cast::Start {
+                        handle: start.handle,
+                        app_id: start.app_id,
+                        parent_window: start.parent_window,
+                        options: start.options,
+                    },
+                )),
+                return_tx,
+            },
+        )?;
+
+        let started = async move {
+            match return_rx.await {
+                Ok(EventResponse::Standard(response, results)) => StreamsStarted {
+                    response,
+                    results: HashMap::try_from(results).unwrap_or_default(),
+                },
+                _ => StreamsStarted {
+                    response: 2,
+                    results: HashMap::new(),
+                },
+            }
+        };
+        // The answer has to get back onto the event loop to reach this session.
+        let session = self.session_handle.clone();
+        xdg_bypass
+            .schedule_then(started, move |xdg_bypass, started| {
+                xdg_bypass.dispatch(EventHandle {
+                    session,
+                    event: Event::StreamsStarted(started),
+                    return_tx: to_return,
+                });
+            })
+            .with_context(|| "Failed to wait for the ScreenCast session")
+    }
+}
+
+/// Whether the permission store holds a grant covering the selected devices.
+async fn stored_grant(
+    connection: &zbus::Connection,
+    app_id: &str,
+    covers: impl Fn(u32) -> bool,
+) -> bool {
+    match grants::lookup(connection, app_id).await {
+        Ok(Some(granted)) if covers(granted) => {
+            info!(
+                "[RemoteDesktop.Start] {} was granted these devices before.",
+                app_id
+            );
+            true
+        }
+        Ok(_) => false,
+        Err(e) => {
+            error!("[RemoteDesktop.Start] Failed to look up grants: {}", e);
+            false
+        }
+    }
+}
+
+/// Keys this session is holding down, so a keysym never presses or releases a
+/// modifier the client is holding itself.
+#[derive(Default)]
+struct HeldKeys(HashSet<KeyCode>);
+
+impl HeldKeys {
+    fn set(&mut self, keycode: KeyCode, pressed: bool) {
+        if pressed {
+            self.0.insert(keycode);
+        } else {
+            self.0.remove(&keycode);
+        }
+    }
+
+    /// Batches of events typing `combo`. Missing modifiers are pressed around
+    /// the key press and released right after it.
+    fn keysym_events(&mut self, combo: KeyCombo, pressed: bool) -> Vec<Vec<InputEvent>> {
+        let key =
+            |keycode: KeyCode, value: i32| InputEvent::new(EventType::KEY.0, keycode.0, value);
+
+        self.set(combo.keycode, pressed);
+        if !pressed {
+            return vec![vec![key(combo.keycode, 0)]];
+        }
+
+        let modifiers = [
+            (combo.shift, KeyCode::KEY_LEFTSHIFT, KeyCode::KEY_RIGHTSHIFT),
+            (
+                combo.level_three,
+                KeyCode::KEY_RIGHTALT,
+                KeyCode::KEY_RIGHTALT,
+            ),
+        ]
+        .into_iter()
+        .filter(|(needed, left, right)| {
+            *needed && !self.0.contains(left) && !self.0.contains(right)
+        })
+        .map(|(_, modifier, _)| modifier)
+        .collect::<Vec<_>>();
+
+        if modifiers.is_empty() {
+            return vec![vec![key(combo.keycode, 1)]];
+        }
+        vec![
+            modifiers.iter().map(|modifier| key(*modifier, 1)).collect(),
+            vec![key(combo.keycode, 1)],
+            modifiers
+                .iter()
+                .rev()
+                .map(|modifier| key(*modifier, 0))
+                .collect(),
+        ]
+    }
 }
 
 impl EventHandler for RemoteDesktopServer {
@@ -32,96 +644,319 @@ impl EventHandler for RemoteDesktopServer {
         event_handle: EventHandle,
     ) -> anyhow::Result<()> {
         let EventHandle {
+            session,
             event,
             return_tx: to_return,
-            ..
         } = event_handle;
 
         match event {
+            Event::CreateSession(create_session) => {
+                self.app_id = create_session.app_id;
+                return_response(
+                    to_return,
+                    EventResponse::Standard(0, empty_results()),
+                    "RemoteDesktop.CreateSession",
+                );
+            }
+            Event::Close => {
+                if let Some(token) = self.eis.take() {
+                    self.loop_handle.remove(token);
+                }
+                // Dropping the devices removes them from the kernel.
+                self.device = None;
+                self.absolute_pointer = None;
+                self.touchscreen = None;
+                self.held_keys = HeldKeys::default();
+                if let Some(mut screen_cast) = self.screen_cast.take() {
+                    // Closes the ScreenCast session at the destination as well.
+                    let (return_tx, _) = oneshot::channel();
+                    let close = EventHandle {
+                        session,
+                        event: Event::Close,
+                        return_tx,
+                    };
+                    if let Err(e) = screen_cast.handle(xdg_bypass, close) {
+                        error!("[RemoteDesktop.Close] {:#}", e);
+                    }
+                }
+                return_response(
+                    to_return,
+                    EventResponse::Standard(0, empty_results()),
+                    "RemoteDesktop.Close",
+                );
+            }
+            Event::CloseRequest(close_request)
+                if self
+                    .pending_consent
+                    .as_ref()
+                    .is_some_and(|(handle, _)| *handle == close_request.handle) =>
+            {
+                // The prompt is withdrawn and the pending `Start` answered with
+                // response code 1 once that went through.
+                if let Some((_, cancel)) = self.pending_consent.take() {
+                    let _ = cancel.send(());
+                }
+                return_response(
+                    to_return,
+                    EventResponse::Standard(0, empty_results()),
+                    "RemoteDesktop.CloseRequest",
+                );
+            }
+            Event::CloseRequest(close_request) => match &mut self.screen_cast {
+                // Only a proxied ScreenCast call can still be pending, the
+                // backend answers it with response code 1.
+                Some(screen_cast) => {
+                    screen_cast.handle(
+                        xdg_bypass,
+                        EventHandle {
+                            session,
+                            event: Event::CloseRequest(close_request),
+                            return_tx: to_return,
+                        },
+                    )?;
+                }
+                None => {
+                    // Every request is answered as soon as it arrives, so there is
+                    // never a pending call left to cancel.
+                    return_response(
+                        to_return,
+                        EventResponse::Standard(0, empty_results()),
+                        "RemoteDesktop.CloseRequest",
+                    );
+                }
+            },
+            Event::ConsentAnswered(answered) => {
+                self.pending_consent = None;
+                match answered.answer {
+                    consent::Answer::Denied => {
+                        info!(
+                            "[RemoteDesktop.Start] Session {} of {} was not approved.",
+                            self.session_handle, self.app_id
+                        );
+                        return_response(
+                            to_return,
+                            EventResponse::Standard(1, empty_results()),
+                            "RemoteDesktop.Start",
+                        );
+                        return Ok(());
+                    }
+                    consent::Answer::Approved => {
+                        if !self.app_id.is_empty() {
+                            self.remember_grant(xdg_bypass);
+                        }
+                    }
+                    consent::Answer::Granted => {}
+                }
+                return self.start(xdg_bypass, answered.start, to_return);
+            }
+            Event::SelectionRestored(restored) => {
+                match restored.device_types {
+                    Some(device_types) => self.restore(xdg_bypass, device_types),
+                    None => info!(
+                        "[RemoteDesktop.SelectDevices] Restore token of {} is unknown or used.",
+                        self.app_id
+                    ),
+                }
+                return_response(
+                    to_return,
+                    EventResponse::Standard(0, empty_results()),
+                    "RemoteDesktop.SelectDevices",
+                );
+            }
+            Event::StreamsStarted(started) => {
+                if started.response != 0 {
+                    error!(
+                        "[RemoteDesktop.Start] ScreenCast of session {} ended with {}",
+                        self.session_handle, started.response
+                    );
+                    return_response(
+                        to_return,
+                        EventResponse::Standard(started.response, empty_results()),
+                        "RemoteDesktop.Start",
+                    );
+                    return Ok(());
+                }
+
+                self.streams = geometry::screen_cast_streams(&started.results);
+                debug!(
+                    "[RemoteDesktop.Start] Session {} streams: {:?}",
+                    self.session_handle, self.streams
+                );
+                match self.build_devices() {
+                    Ok(()) => {
+                        let streams = started
+                            .results
+                            .get("streams")
+                            .and_then(|streams| streams.try_clone().ok());
+                        return_response(
+                            to_return,
+                            EventResponse::Standard(0, self.start_results(xdg_bypass, streams)),
+                            "RemoteDesktop.Start",
+                        );
+                    }
+                    Err(e) => {
+                        error!("[RemoteDesktop.Start] {:#}", e);
+                        return_response(
+                            to_return,
+                            EventResponse::Standard(2, empty_results()),
+                            "RemoteDesktop.Start",
+                        );
+                    }
+                }
+            }
+            Event::ScreenCast(screen_cast_event) => match screen_cast_event {
+                ScreenCastEvent::SelectSources(select_sources) => {
+                    if self.screen_cast.is_none() {
+                        let Some(destination) = xdg_bypass
+                            .config
+                            .proxy_destinations(Interface::ScreenCast)
+                            .next()
+                        else {
+                            error!(
+                                "[ScreenCast.SelectSources] ScreenCast is not proxied, session {} has no streams.",
+                                self.session_handle
+                            );
+                            return_response(
+                                to_return,
+                                EventResponse::Standard(2, empty_results()),
+                                "ScreenCast.SelectSources",
+                            );
+                            return Ok(());
+                        };
+                        let destination = destination.clone();
+                        self.screen_cast = Some(ScreenCastProxy::with_destination(
+                            xdg_bypass,
+                            self.session_handle.clone(),
+                            &destination,
+                            DownstreamPaths::new(),
+                            false,
+                        )?);
+                    }
+                    if let Some(screen_cast) = &mut self.screen_cast {
+                        screen_cast.handle(
+                            xdg_bypass,
+                            EventHandle {
+                                session,
+                                event: Event::ScreenCast(ScreenCastEvent::SelectSources(
+                                    select_sources,
+                                )),
+                                return_tx: to_return,
+                            },
+                        )?;
+                    }
+                }
+                ScreenCastEvent::OpenPipeWireRemote(open_pipe_wire_remote) => {
+                    match &mut self.screen_cast {
+                        Some(screen_cast) => {
+                            screen_cast.handle(
+                                xdg_bypass,
+                                EventHandle {
+                                    session,
+                                    event: Event::ScreenCast(ScreenCastEvent::OpenPipeWireRemote(
+                                        open_pipe_wire_remote,
+                                    )),
+                                    return_tx: to_return,
+                                },
+                            )?;
+                        }
+                        None => {
+                            error!(
+                                "[ScreenCast.OpenPipeWireRemote] Session {} has no streams.",
+                                self.session_handle
+                            );
+                            return_response(
+                                to_return,
+                                EventResponse::Standard(2, empty_results()),
+                                "ScreenCast.OpenPipeWireRemote",
+                            );
+                        }
+                    }
+                }
+                _ => {
+                    // A RemoteDesktop session is started through RemoteDesktop.Start.
+                    error!(
+                        "[ScreenCast] Unsupported call on RemoteDesktop session {}.",
+                        self.session_handle
+                    );
+                    return_response(
+                        to_return,
+                        EventResponse::Standard(2, empty_results()),
+                        "ScreenCast",
+                    );
+                }
+            },
             Event::RemoteDesktop(remote_desktop_event) => match remote_desktop_event {
                 RemoteDesktopEvent::SelectDevices(select_devices) => {
-                    if let Some(types) = select_devices
+                    // Both options are optional, no types means every available type.
+                    let types = select_devices
                         .options
                         .get("types")
-                        .and_then(|v| v.clone().try_into().ok())
-                    {
-                        if types > 3 {
-                            error!("[RemoteDesktop.SelectDevices] Unsupport touch device now.");
+                        .map(|v| u32::try_from(v.clone()))
+                        .unwrap_or(Ok(AVAILABLE_DEVICE_TYPES));
+                    let persist_mode = select_devices
+                        .options
+                        .get("persist_mode")
+                        .map(|v| u32::try_from(v.clone()))
+                        .unwrap_or(Ok(0));
+                    // Unknown or malformed tokens are ignored, the user is asked instead.
+                    let restore_token = select_devices
+                        .options
+                        .get("restore_token")
+                        .and_then(|v| String::try_from(v.clone()).ok())
+                        .filter(|token| grants::is_token(token));
+
+                    match (types, persist_mode) {
+                        (Ok(types), Ok(persist_mode))
+                            if types & !AVAILABLE_DEVICE_TYPES == 0 && persist_mode <= 2 =>
+                        {
+                            self.device_types = types;
+                            self.persist_mode = persist_mode;
+                            self.restored = false;
+                            debug!(
+                                "[RemoteDesktop.SelectDevices] Session {} of {} selected types {} with persist mode {}, restore token: {}",
+                                self.session_handle,
+                                self.app_id,
+                                types,
+                                persist_mode,
+                                restore_token.is_some()
+                            );
+                            // Tokens are never issued to apps without an app id.
+                            if let Some(token) = restore_token
+                                && !self.app_id.is_empty()
+                            {
+                                return self.restore_selection(xdg_bypass, token, to_return);
+                            }
                             return_response(
                                 to_return,
-                                EventResponse::Standard(
-                                    2,
-                                    Value::from("Input vailed is uncorect.").try_into_owned()?,
-                                ),
+                                EventResponse::Standard(0, empty_results()),
                                 "RemoteDesktop.SelectDevices",
                             );
-                        } else {
-                            self.device_select = types;
+                        }
+                        _ => {
+                            error!("[RemoteDesktop.SelectDevices] Failed when realizing inputs.");
                             return_response(
                                 to_return,
-                                EventResponse::Standard(
-                                    0,
-                                    OwnedValue::from(HashMap::<String, zvariant::OwnedValue>::new()),
-                                ),
+                                EventResponse::Standard(2, empty_results()),
                                 "RemoteDesktop.SelectDevices",
                             );
                         }
-                    } else {
-                        error!("[RemoteDesktop.SelectDevices] Failed when realizing inputs.");
-                        return_response(
-                            to_return,
-                            EventResponse::Standard(
-                                2,
-                                Value::from("Input vailed is uncorect.").try_into_owned()?,
-                            ),
-                            "RemoteDesktop.SelectDevices",
-                        );
                     }
                 }
                 RemoteDesktopEvent::Start(start) => {
-                    let mut keys = AttributeSet::<KeyCode>::new();
-                    if self.device_select & 1 != 0 {
-                        for i in 0x000..0x2e7 {
-                            keys.insert(KeyCode::new(i as u16));
-                        }
-                    }
-                    let mut mouse_axis = AttributeSet::<RelativeAxisCode>::new();
-                    if self.device_select & 2 != 0 {
-                        for i in 0x00..0x0c {
-                            mouse_axis.insert(RelativeAxisCode(i as u16));
-                        }
-                    }
-
-                    let mut builder = VirtualDevice::builder()?;
-                    builder = builder.name("xdg-desktop-portal-bypass vitural input device");
-
-                    debug!("[RemoteDesktop.Start] Try to build virtual device.");
-                    if let Ok(device) = builder
-                        .with_keys(&keys)?
-                        .with_relative_axes(&mouse_axis)?
-                        .build()
-                        .with_context(|| "[RemoteDesktop.Start] Failed to create virtual device.")
-                    {
-                        self.device = Some(device);
-                        return_response(
-                            to_return,
-                            EventResponse::Standard(
-                                0,
-                                OwnedValue::from(HashMap::<String, zvariant::OwnedValue>::new()),
-                            ),
-                            "RemoteDesktop.Start",
+                    if self.pending_consent.is_some() {
+                        error!(
+                            "[RemoteDesktop.Start] Session {} is already waiting for the user.",
+                            self.session_handle
                         );
-                    } else {
-                        error!("[RemoteDesktop.Start] Failed to create virtual device.");
                         return_response(
                             to_return,
-                            EventResponse::Standard(
-                                2,
-                                Value::from("Failed to create virtual device.").try_into_owned()?,
-                            ),
+                            EventResponse::Standard(2, empty_results()),
                             "RemoteDesktop.Start",
                         );
-                    };
+                        return Ok(());
+                    }
+                    debug!("[RemoteDesktop.Start] Asking the user for consent.");
+                    return self.request_consent(xdg_bypass, start, to_return);
                 }
                 RemoteDesktopEvent::NotifyPointerMotion(notify_pointer_motion) => {
                     if let Some(device) = &mut self.device {
@@ -144,23 +979,43 @@ impl EventHandler for RemoteDesktopServer {
                     }
                 }
                 RemoteDesktopEvent::NotifyPointerMotionAbsolute(notify_pointer_motion_absolute) => {
-                    if let Some(device) = &mut self.device {
-                        let _ = device.emit(&[
-                            InputEvent::new(
-                                EventType::ABSOLUTE.0,
-                                0x00,
-                                notify_pointer_motion_absolute.x as i32,
-                            ), // ABS_X
-                            InputEvent::new(
-                                EventType::ABSOLUTE.0,
-                                0x01,
-                                notify_pointer_motion_absolute.y as i32,
-                            ), // ABS_Y
-                        ]);
-                    } else {
-                        error!(
-                            "[RemoteDesktop.NotifyPointerMotionAbsolute] No virtual device was created."
-                        );
+                    let stream = self.stream(notify_pointer_motion_absolute.stream);
+                    match (&mut self.absolute_pointer, stream) {
+                        (Some((device, mapping)), Some(stream)) => {
+                            let (x, y) = mapping.device_position(
+                                stream,
+                                notify_pointer_motion_absolute.x,
+                                notify_pointer_motion_absolute.y,
+                            );
+                            let _ = device.emit(&[
+                                InputEvent::new(
+                                    EventType::ABSOLUTE.0,
+                                    AbsoluteAxisCode::ABS_X.0,
+                                    x,
+                                ),
+                                InputEvent::new(
+                                    EventType::ABSOLUTE.0,
+                                    AbsoluteAxisCode::ABS_Y.0,
+                                    y,
+                                ),
+                            ]);
+                        }
+                        (Some(_), None) => {
+                            error!(
+                                "[RemoteDesktop.NotifyPointerMotionAbsolute] Unknown stream {}.",
+                                notify_pointer_motion_absolute.stream
+                            );
+                            return_response(
+                                to_return,
+                                EventResponse::Standard(2, empty_results()),
+                                "RemoteDesktop.NotifyPointerMotionAbsolute",
+                            );
+                        }
+                        (None, _) => {
+                            error!(
+                                "[RemoteDesktop.NotifyPointerMotionAbsolute] No virtual device was created."
+                            );
+                        }
                     }
                 }
                 RemoteDesktopEvent::NotifyPointerButton(notify_pointer_button) => {
@@ -185,19 +1040,21 @@ impl EventHandler for RemoteDesktopServer {
                 }
                 RemoteDesktopEvent::NotifyPointerAxis(notify_pointer_axis) => {
                     if let Some(device) = &mut self.device {
+                        // Portal axes grow down and right, the wheel turns up
+                        // for positive values.
                         let mut events = Vec::new();
-                        if notify_pointer_axis.dx != 0.0 {
+                        if notify_pointer_axis.dy != 0.0 {
                             events.push(InputEvent::new(
                                 EventType::RELATIVE.0,
                                 RelativeAxisCode::REL_WHEEL.0,
-                                notify_pointer_axis.dx as i32,
+                                -notify_pointer_axis.dy as i32,
                             ));
                         }
-                        if notify_pointer_axis.dy != 0.0 {
+                        if notify_pointer_axis.dx != 0.0 {
                             events.push(InputEvent::new(
                                 EventType::RELATIVE.0,
                                 RelativeAxisCode::REL_HWHEEL.0,
-                                notify_pointer_axis.dy as i32,
+                                notify_pointer_axis.dx as i32,
                             ));
                         }
                         let _ = device.emit(&events);
@@ -207,11 +1064,12 @@ impl EventHandler for RemoteDesktopServer {
                 }
                 RemoteDesktopEvent::NotifyPointerAxisDiscrete(notify_pointer_axis_discrete) => {
                     if let Some(device) = &mut self.device {
+                        // Positive steps scroll down or right, like `NotifyPointerAxis`.
                         let events = if notify_pointer_axis_discrete.axis == 0 {
                             vec![InputEvent::new(
                                 EventType::RELATIVE.0,
                                 RelativeAxisCode::REL_WHEEL.0,
-                                notify_pointer_axis_discrete.steps,
+                                -notify_pointer_axis_discrete.steps,
                             )]
                         } else {
                             vec![InputEvent::new(
@@ -229,9 +1087,12 @@ impl EventHandler for RemoteDesktopServer {
                 }
                 RemoteDesktopEvent::NotifyKeyboardKeycode(notify_keyboard_keycode) => {
                     if let Some(device) = &mut self.device {
+                        let keycode = KeyCode::new(notify_keyboard_keycode.keycode as u16);
+                        let pressed = notify_keyboard_keycode.state != 0;
+                        self.held_keys.set(keycode, pressed);
                         let _ = device.emit(&[InputEvent::new(
                             EventType::KEY.0,
-                            notify_keyboard_keycode.keycode as u16,
+                            keycode.0,
                             notify_keyboard_keycode.state as i32,
                         )]);
                     } else {
@@ -240,36 +1101,178 @@ impl EventHandler for RemoteDesktopServer {
                         );
                     }
                 }
-                RemoteDesktopEvent::NotifyKeyboardKeysym(_) => {
-                    let mut map = HashMap::new();
-                    map.insert("error", Value::from("Don't support Keysym event yet."));
-                    return_response(
-                        to_return,
-                        EventResponse::Standard(2, OwnedValue::from(map)),
-                        "RemoteDesktop.NotifyKeyboardKeysym",
-                    );
-                    error!("[RemoteDesktop.NotifyKeyboardKeysym] No virtual device was created.");
+                RemoteDesktopEvent::NotifyKeyboardKeysym(notify_keyboard_keysym) => {
+                    let combo = self
+                        .keysyms
+                        .as_ref()
+                        .and_then(|keysyms| keysyms.lookup(notify_keyboard_keysym.keysym as u32));
+                    match (&mut self.device, combo) {
+                        (Some(device), Some(combo)) => {
+                            let pressed = notify_keyboard_keysym.state != 0;
+                            for events in self.held_keys.keysym_events(combo, pressed) {
+                                let _ = device.emit(&events);
+                            }
+                        }
+                        (Some(_), None) if self.keysyms.is_none() => {
+                            error!(
+                                "[RemoteDesktop.NotifyKeyboardKeysym] Keymap unavailable, keysym {:#x} cannot be typed.",
+                                Payload(&notify_keyboard_keysym.keysym)
+                            );
+                            return_response(
+                                to_return,
+                                EventResponse::Standard(2, empty_results()),
+                                "RemoteDesktop.NotifyKeyboardKeysym",
+                            );
+                        }
+                        (Some(_), None) => {
+                            error!(
+                                "[RemoteDesktop.NotifyKeyboardKeysym] No key produces keysym {:#x}.",
+                                Payload(&notify_keyboard_keysym.keysym)
+                            );
+                            return_response(
+                                to_return,
+                                EventResponse::Standard(2, empty_results()),
+                                "RemoteDesktop.NotifyKeyboardKeysym",
+                            );
+                        }
+                        (None, _) => {
+                            error!(
+                                "[RemoteDesktop.NotifyKeyboardKeysym] No virtual device was created."
+                            );
+                        }
+                    }
+                }
+                RemoteDesktopEvent::NotifyTouchDown(notify_touch_down) => {
+                    let stream = self.stream(notify_touch_down.stream);
+                    let result = match (&mut self.touchscreen, stream) {
+                        (Some(touchscreen), Some(stream)) => touchscreen.down(
+                            notify_touch_down.slot,
+                            stream,
+                            notify_touch_down.x,
+                            notify_touch_down.y,
+                        ),
+                        (Some(_), None) => Err(anyhow::anyhow!(
+                            "Unknown stream {}.",
+                            notify_touch_down.stream
+                        )),
+                        (None, _) => Err(anyhow::anyhow!("No virtual touchscreen was created.")),
+                    };
+                    if let Err(e) = result {
+                        error!("[RemoteDesktop.NotifyTouchDown] {:#}", e);
+                        return_response(
+                            to_return,
+                            EventResponse::Standard(2, empty_results()),
+                            "RemoteDesktop.NotifyTouchDown",
+                        );
+                    }
+                }
+                RemoteDesktopEvent::NotifyTouchMotion(notify_touch_motion) => {
+                    let stream = self.stream(notify_touch_motion.stream);
+                    let result = match (&mut self.touchscreen, stream) {
+                        (Some(touchscreen), Some(stream)) => touchscreen.motion(
+                            notify_touch_motion.slot,
+                            stream,
+                            notify_touch_motion.x,
+                            notify_touch_motion.y,
+                        ),
+                        (Some(_), None) => Err(anyhow::anyhow!(
+                            "Unknown stream {}.",
+                            notify_touch_motion.stream
+                        )),
+                        (None, _) => Err(anyhow::anyhow!("No virtual touchscreen was created.")),
+                    };
+                    if let Err(e) = result {
+                        error!("[RemoteDesktop.NotifyTouchMotion] {:#}", e);
+                        return_response(
+                            to_return,
+                            EventResponse::Standard(2, empty_results()),
+                            "RemoteDesktop.NotifyTouchMotion",
+                        );
+                    }
+                }
+                RemoteDesktopEvent::NotifyTouchUp(notify_touch_up) => {
+                    let result = match &mut self.touchscreen {
+                        Some(touchscreen) => touchscreen.up(notify_touch_up.slot),
+                        None => Err(anyhow::anyhow!("No virtual touchscreen was created.")),
+                    };
+                    if let Err(e) = result {
+                        error!("[RemoteDesktop.NotifyTouchUp] {:#}", e);
+                        return_response(
+                            to_return,
+                            EventResponse::Standard(2, empty_results()),
+                            "RemoteDesktop.NotifyTouchUp",
+                        );
+                    }
+                }
+                RemoteDesktopEvent::ConnectToEIS(_) => {
+                    // Only once per session, and only on the devices created by `Start`.
+                    if !self.has_devices() || self.eis.is_some() {
+                        error!(
+                            "[RemoteDesktop.ConnectToEIS] Session {} is not started or already connected.",
+                            self.session_handle
+                        );
+                        return_response(
+                            to_return,
+                            EventResponse::Standard(2, empty_results()),
+                            "RemoteDesktop.ConnectToEIS",
+                        );
+                        return Ok(());
+                    }
+
+                    match eis::connect(
+                        &xdg_bypass.loop_handle,
+                        self.session_handle.clone(),
+                        self.device_types,
+                        self.streams.clone(),
+                    ) {
+                        Ok((fd, token)) => {
+                            debug!(
+                                "[RemoteDesktop.ConnectToEIS] Session {} of {} connected to EIS",
+                                self.session_handle, self.app_id
+                            );
+                            self.eis = Some(token);
+                            return_response(
+                                to_return,
+                                EventResponse::Fd(fd),
+                                "RemoteDesktop.ConnectToEIS",
+                            );
+                        }
+                        Err(e) => {
+                            error!("[RemoteDesktop.ConnectToEIS] {:#}", e);
+                            return_response(
+                                to_return,
+                                EventResponse::Standard(2, empty_results()),
+                                "RemoteDesktop.ConnectToEIS",
+                            );
+                        }
+                    }
                 }
                 RemoteDesktopEvent::GetPropertiesAvilableDeviceTypes => {
                     return_response(
                         to_return,
-                        EventResponse::Value(OwnedValue::from(3u32)), // Keyboard(1) | Mouse(2)
+                        EventResponse::Value(OwnedValue::from(AVAILABLE_DEVICE_TYPES)),
                         "RemoteDesktop.GetPropertiesAvilableDeviceTypes",
                     );
                 }
                 RemoteDesktopEvent::GetPropertiesVersion => {
                     return_response(
                         to_return,
-                        EventResponse::Value(OwnedValue::from(1u32)),
+                        // Version 2 added `ConnectToEIS`.
+                        EventResponse::Value(OwnedValue::from(2u32)),
                         "RemoteDesktop.GetPropertiesVersion",
                     );
                 }
             },
-            _ => anyhow::bail!("Must be a remote desktop event send in the constructor"),
         }
         Ok(())
     }
 
+    fn uses_destination(&self, service_name: &str) -> bool {
+        self.screen_cast
+            .as_ref()
+            .is_some_and(|screen_cast| screen_cast.uses_destination(service_name))
+    }
+
     fn new(
         xdg_bypass: &mut crate::event_handler::XdgBypass,
         session: zbus::zvariant::OwnedObjectPath,
@@ -277,6 +1280,24 @@ impl EventHandler for RemoteDesktopServer {
     where
         Self: Sized,
     {
-        unimplemented!()
+        Ok(Box::new(Self {
+            session_handle: session,
+            app_id: String::new(),
+            device_types: 0,
+            persist_mode: 0,
+            restored: false,
+            device: None,
+            absolute_pointer: None,
+            touchscreen: None,
+            keysyms: None,
+            held_keys: HeldKeys::default(),
+            options: xdg_bypass.config.devices.clone(),
+            streams: Vec::new(),
+            eis: None,
+            loop_handle: xdg_bypass.loop_handle.clone(),
+            screen_cast: None,
+            consent: xdg_bypass.config.consent.clone(),
+            pending_consent: None,
+        }))
     }
 }
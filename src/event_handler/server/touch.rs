@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+
+use anyhow::Context;
+use evdev::AbsInfo;
+use evdev::AbsoluteAxisCode;
+use evdev::AttributeSet;
+use evdev::EventType;
+use evdev::InputEvent;
+use evdev::KeyCode;
+use evdev::PropType;
+use evdev::UinputAbsSetup;
+use evdev::uinput::VirtualDevice;
+
+use crate::event_handler::server::geometry::{AbsoluteMapping, Rect};
+
+/// Number of simultaneous touch points the device advertises.
+pub const MAX_SLOTS: u32 = 10;
+
+/// Multitouch (protocol type B) uinput device for the `NotifyTouch*` methods.
+pub struct Touchscreen {
+    device: VirtualDevice,
+    mapping: AbsoluteMapping,
+    /// Tracking id of every slot currently touching.
+    slots: HashMap<u32, i32>,
+    next_tracking_id: i32,
+}
+
+impl Touchscreen {
+    pub fn new(name: &str, mapping: AbsoluteMapping) -> anyhow::Result<Self> {
+        let mut keys = AttributeSet::<KeyCode>::new();
+        keys.insert(KeyCode::BTN_TOUCH);
+
+        let mut properties = AttributeSet::<PropType>::new();
+        properties.insert(PropType::DIRECT);
+
+        let (max_x, max_y) = mapping.maximum();
+        let axis = |code, maximum| UinputAbsSetup::new(code, AbsInfo::new(0, 0, maximum, 0, 0, 1));
+
+        let device = VirtualDevice::builder()?
+            .name(name)
+            .with_keys(&keys)?
+            .with_properties(&properties)?
+            .with_absolute_axis(&axis(AbsoluteAxisCode::ABS_X, max_x))?
+            .with_absolute_axis(&axis(AbsoluteAxisCode::ABS_Y, max_y))?
+            .with_absolute_axis(&axis(AbsoluteAxisCode::ABS_MT_SLOT, MAX_SLOTS as i32 - 1))?
+            .with_absolute_axis(&axis(AbsoluteAxisCode::ABS_MT_TRACKING_ID, i32::MAX))?
+            .with_absolute_axis(&axis(AbsoluteAxisCode::ABS_MT_POSITION_X, max_x))?
+            .with_absolute_axis(&axis(AbsoluteAxisCode::ABS_MT_POSITION_Y, max_y))?
+            .build()
+            .with_context(|| "Failed to create virtual touchscreen.")?;
+
+        Ok(Self {
+            device,
+            mapping,
+            slots: HashMap::new(),
+            next_tracking_id: 0,
+        })
+    }
+
+    pub fn down(&mut self, slot: u32, stream: Rect, x: f64, y: f64) -> anyhow::Result<()> {
+        if slot >= MAX_SLOTS {
+            anyhow::bail!("Touch slot {} is out of range", slot);
+        }
+        if self.slots.contains_key(&slot) {
+            anyhow::bail!("Touch slot {} is already down", slot);
+        }
+
+        let tracking_id = self.next_tracking_id;
+        self.next_tracking_id = self.next_tracking_id.wrapping_add(1) & i32::MAX;
+        let first = self.slots.is_empty();
+        self.slots.insert(slot, tracking_id);
+
+        let mut events = vec![
+            abs(AbsoluteAxisCode::ABS_MT_SLOT, slot as i32),
+            abs(AbsoluteAxisCode::ABS_MT_TRACKING_ID, tracking_id),
+        ];
+        events.extend(self.position(stream, x, y));
+        if first {
+            events.push(InputEvent::new(EventType::KEY.0, KeyCode::BTN_TOUCH.0, 1));
+        }
+
+        Ok(self.device.emit(&events)?)
+    }
+
+    pub fn motion(&mut self, slot: u32, stream: Rect, x: f64, y: f64) -> anyhow::Result<()> {
+        if !self.slots.contains_key(&slot) {
+            anyhow::bail!("Touch slot {} is not down", slot);
+        }
+
+        let mut events = vec![abs(AbsoluteAxisCode::ABS_MT_SLOT, slot as i32)];
+        events.extend(self.position(stream, x, y));
+
+        Ok(self.device.emit(&events)?)
+    }
+
+    pub fn up(&mut self, slot: u32) -> anyhow::Result<()> {
+        if self.slots.remove(&slot).is_none() {
+            anyhow::bail!("Touch slot {} is not down", slot);
+        }
+
+        let mut events = vec![
+            abs(AbsoluteAxisCode::ABS_MT_SLOT, slot as i32),
+            abs(AbsoluteAxisCode::ABS_MT_TRACKING_ID, -1),
+        ];
+        if self.slots.is_empty() {
+            events.push(InputEvent::new(EventType::KEY.0, KeyCode::BTN_TOUCH.0, 0));
+        }
+
+        Ok(self.device.emit(&events)?)
+    }
+
+    /// Multitouch position of the current slot, mirrored on the single touch axes.
+    fn position(&self, stream: Rect, x: f64, y: f64) -> [InputEvent; 4] {
+        let (x, y) = self.mapping.device_position(stream, x, y);
+        [
+            abs(AbsoluteAxisCode::ABS_MT_POSITION_X, x),
+            abs(AbsoluteAxisCode::ABS_MT_POSITION_Y, y),
+            abs(AbsoluteAxisCode::ABS_X, x),
+            abs(AbsoluteAxisCode::ABS_Y, y),
+        ]
+    }
+}
+
+fn abs(code: AbsoluteAxisCode, value: i32) -> InputEvent {
+    InputEvent::new(EventType::ABSOLUTE.0, code.0, value)
+}
@@ -1,26 +1,78 @@
 use anyhow::Context;
 use calloop::signals::Signals;
 use calloop::{channel, signals::Signal};
-use tracing::info;
+use tracing::{error, info, warn};
+use tracing_subscriber::Layer;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
-use tracing_subscriber::{EnvFilter, fmt};
+use tracing_subscriber::{EnvFilter, fmt, reload};
 
-use crate::event_handler::{EventHandle, WorkingMode, XdgBypass, XdgBypassConfig};
+use crate::cli::{Cli, GrantsCommand};
+use crate::config::{Config, LogFormat};
+use crate::dbus_listener::ListenerOptions;
+use crate::event_handler::events;
+use crate::event_handler::server::{consent, grants};
+use crate::event_handler::{EventHandle, Interface, XdgBypass};
 
+mod cli;
+mod config;
 mod dbus_listener;
 mod event_handler;
 
 fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    if let Some(command) = &cli.grants {
+        return run_grants(command);
+    }
+    let config = Config::load(cli.config.as_deref(), &cli.overrides)?;
+
+    if cli.dry_run {
+        match &config.path {
+            Some(path) => println!("Config file: {}", path.display()),
+            None => println!("Config file: none, using defaults"),
+        }
+        println!("Bus name: {}", cli.bus_name);
+        println!("{:#?}", config.log);
+        println!("{:#?}", config.xdg_bypass);
+        return Ok(());
+    }
+
+    // RUST_LOG wins over the configured level, but not over --log-level,
+    // also across reloads.
+    let env_filter = cli
+        .overrides
+        .log_level
+        .is_none()
+        .then(EnvFilter::try_from_default_env)
+        .and_then(Result::ok);
+    let use_config_level = env_filter.is_none();
+    let (filter, filter_handle) =
+        reload::Layer::new(env_filter.unwrap_or_else(|| EnvFilter::new(&config.log.level)));
+    let layer = match config.log.format {
+        LogFormat::Full => fmt::layer().boxed(),
+        LogFormat::Compact => fmt::layer().compact().boxed(),
+        LogFormat::Pretty => fmt::layer().pretty().boxed(),
+    };
     tracing_subscriber::registry()
-        .with(fmt::layer())
-        .with(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("debug")))
+        .with(filter)
+        .with(layer)
         .try_init()
         .with_context(|| "Failed to init log subscriber")?;
 
-    let config = XdgBypassConfig {
-        remote_desktop_mode: WorkingMode::Server,
-    };
+    match &config.path {
+        Some(path) => info!("Config loaded from {}", path.display()),
+        None => info!("No config file found, using defaults"),
+    }
+    set_log_input(config.log.input);
+    let mut log_config = config.log;
+    let config = config.xdg_bypass;
+
+    // Signals are blocked on the calling thread only, create them before the
+    // D-Bus connections spawn their threads so those inherit the mask.
+    let force_close_signal = Signals::new(&[Signal::SIGINT, Signal::SIGTERM])
+        .with_context(|| "Failed to create stop signal")?;
+    let reload_signal =
+        Signals::new(&[Signal::SIGHUP]).with_context(|| "Failed to create reload signal")?;
 
     let (dbus_listener_tx, dbus_listener_rx) = channel::channel::<EventHandle>();
 
@@ -29,7 +81,22 @@ fn main() -> anyhow::Result<()> {
         .with_context(|| "Failed to create event loop")?;
 
     info!("DBus listener created");
-    let dbus_listener = dbus_listener::DBusListener::new(dbus_listener_tx);
+    let dbus_listener = dbus_listener::DBusListener::new(
+        dbus_listener_tx,
+        &ListenerOptions {
+            bus_name: cli.bus_name.clone(),
+            replace: cli.replace,
+            // Exported once at startup, a reload can't add or drop it.
+            screen_cast: config
+                .proxy_destinations(Interface::ScreenCast)
+                .next()
+                .is_some(),
+        },
+    );
+    if dbus_listener.connection.is_none() {
+        anyhow::bail!("No portal interface could be served on {}", cli.bus_name);
+    }
+    dbus_listener.stop_on_name_lost(&cli.bus_name, event_loop.get_signal());
 
     info!("Async executor created");
     let (executor, scheduler) =
@@ -40,7 +107,18 @@ fn main() -> anyhow::Result<()> {
     })
     .with_context(|| "Failed when create DBus Connection for proxy")?;
     info!("Event handler created");
-    let mut event_handler = XdgBypass::new(config, event_loop.get_signal(), scheduler, connection);
+    let listener_connection = dbus_listener
+        .connection
+        .as_ref()
+        .map(|connection| connection.inner().clone());
+    let mut event_handler = XdgBypass::new(
+        config,
+        event_loop.get_signal(),
+        event_loop.handle(),
+        scheduler,
+        connection,
+        listener_connection,
+    );
 
     event_loop
         .handle()
@@ -54,9 +132,6 @@ fn main() -> anyhow::Result<()> {
         .map_err(|e| e.error)
         .with_context(|| "Failed to listen for DBus events")?;
 
-    let force_close_signal = Signals::new(&[Signal::SIGINT, Signal::SIGTERM])
-        .with_context(|| "Failed to create stop signal")?;
-
     event_loop
         .handle()
         .insert_source(executor, |_, _, _| {})
@@ -70,6 +145,36 @@ fn main() -> anyhow::Result<()> {
         })
         .with_context(|| "Failed to listen for stop signals")?;
 
+    event_loop
+        .handle()
+        .insert_source(reload_signal, move |_, _, state| {
+            info!("SIGHUP received, reloading config");
+            let config = match Config::load(cli.config.as_deref(), &cli.overrides) {
+                Ok(config) => config,
+                Err(e) => {
+                    error!("Failed to reload config, keeping the current one: {:#}", e);
+                    return;
+                }
+            };
+
+            if use_config_level
+                && config.log.level != log_config.level
+                && let Err(e) = filter_handle.reload(EnvFilter::new(&config.log.level))
+            {
+                error!("Failed to apply log level {}: {}", config.log.level, e);
+            }
+            if config.log.format != log_config.format {
+                warn!("Log format changes only apply after a restart");
+            }
+            if config.log.input != log_config.input {
+                set_log_input(config.log.input);
+            }
+            log_config = config.log;
+
+            state.reload_config(config.xdg_bypass);
+        })
+        .with_context(|| "Failed to listen for reload signals")?;
+
     info!("Event loop started");
     event_loop
         .run(None, &mut event_handler, |_| {})
@@ -77,3 +182,43 @@ fn main() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+/// Run a `grants` subcommand against the permission store of the session bus.
+fn run_grants(command: &GrantsCommand) -> anyhow::Result<()> {
+    futures::executor::block_on(async {
+        let connection = zbus::Connection::session()
+            .await
+            .with_context(|| "Failed to connect to the session bus")?;
+        match command {
+            GrantsCommand::List => {
+                let mut grants: Vec<(String, u32)> = grants::list(&connection)
+                    .await
+                    .with_context(|| "Failed to read the grants")?
+                    .into_iter()
+                    .collect();
+                if grants.is_empty() {
+                    println!("No grants stored");
+                }
+                grants.sort();
+                for (app_id, device_types) in grants {
+                    println!("{}\t{}", app_id, consent::device_names(device_types));
+                }
+            }
+            GrantsCommand::Revoke(app_id) => {
+                grants::revoke(&connection, app_id)
+                    .await
+                    .with_context(|| format!("Failed to revoke the grant of {}", app_id))?;
+                println!("Revoked the grant of {}", app_id);
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Log input payloads as `input` asks, warning whenever they are.
+fn set_log_input(input: bool) {
+    if input {
+        warn!("Input events are logged in full, including everything typed remotely");
+    }
+    events::set_log_input(input);
+}